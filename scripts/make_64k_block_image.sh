@@ -0,0 +1,11 @@
+#!/bin/sh
+# Generates ext2fs_64k_blocks.bin, an image with 64 blocks of 64KiB. Directory entries that span
+# a whole block have a record length of 65536, which doesn't fit the 16 bit field on disk.
+set -e
+
+OUT="${1:-ext2fs_64k_blocks.bin}"
+
+rm -f "$OUT"
+E2FSPROGS_FAKE_TIME=1623268620 mke2fs -q -F -t ext2 -b 65536 -N 16 -O ^resize_inode \
+    -U 2b3c4d5e-6f70-4a1b-8c2d-3e4f5a6b7c8d -E hash_seed=9a8b7c6d-5e4f-4031-a2b3-c4d5e6f7a8b9 \
+    "$OUT" 4M
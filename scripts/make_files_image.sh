@@ -0,0 +1,46 @@
+#!/bin/sh
+# Generates ext2fs_files.bin, the test image with regular files, directories and symlinks.
+# Requires mke2fs from e2fsprogs 1.43 or newer (for -d).
+set -e
+
+OUT="${1:-ext2fs_files.bin}"
+ROOT="$(mktemp -d)"
+trap 'rm -rf "$ROOT"' EXIT
+
+LONG_NAME="a_very_long_file_name_that_makes_the_symlink_target_exceed_sixty_bytes.txt"
+
+printf 'Hello, world!\n' > "$ROOT/hello.txt"
+mkdir -p "$ROOT/dir/sub" "$ROOT/dir/many"
+printf 'nested\n' > "$ROOT/dir/nested.txt"
+printf 'deep\n' > "$ROOT/dir/sub/deep.txt"
+printf 'long\n' > "$ROOT/dir/$LONG_NAME"
+
+# 300000 bytes need double indirect blocks with 1KiB blocks
+python3 -c 'import sys; sys.stdout.buffer.write(bytes((i * 7) % 251 for i in range(300000)))' \
+    > "$ROOT/big.bin"
+
+# One byte at the start and one at 100KiB, with a hole in between
+printf 'A' > "$ROOT/sparse.bin"
+printf 'B' | dd of="$ROOT/sparse.bin" bs=1 seek=102400 conv=notrunc 2>/dev/null
+
+# Enough entries to span several directory blocks
+i=0
+while [ $i -lt 100 ]; do
+    printf '%d\n' $i > "$ROOT/dir/many/file_$(printf '%03d' $i)"
+    i=$((i + 1))
+done
+
+ln -s hello.txt "$ROOT/link"
+ln -s "dir/$LONG_NAME" "$ROOT/long_link"
+ln -s ../hello.txt "$ROOT/dir/up"
+ln -s dir "$ROOT/dir_link"
+ln -s loop_b "$ROOT/loop_a"
+ln -s loop_a "$ROOT/loop_b"
+mkfifo "$ROOT/fifo"
+
+find "$ROOT" -exec touch -h -d @1623268620 {} +
+
+rm -f "$OUT"
+E2FSPROGS_FAKE_TIME=1623268620 mke2fs -q -t ext2 -b 1024 -N 256 -g 2048 \
+    -U 7c4c0d1e-4b3a-4f4e-9a4b-5e2d1c0b9a87 -E hash_seed=1b9e6d2f-0a3c-4e5d-8f7a-6b4c3d2e1f00 \
+    -d "$ROOT" "$OUT" 4M
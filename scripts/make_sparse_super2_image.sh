@@ -0,0 +1,13 @@
+#!/bin/sh
+# Generates ext2fs_sparse_super2.bin, a 1KiB-block image with 8 groups of 512 blocks and the
+# sparse_super2 feature. The only superblock backups are in groups 1 and 7 (`s_backup_bgs`),
+# while sparse_super would also place them in groups 3 and 5.
+# Requires mke2fs from e2fsprogs 1.42.10 or newer (for sparse_super2).
+set -e
+
+OUT="${1:-ext2fs_sparse_super2.bin}"
+
+rm -f "$OUT"
+E2FSPROGS_FAKE_TIME=1623268620 mke2fs -q -t ext2 -b 1024 -N 64 -g 512 -O sparse_super2 \
+    -U 5e4d3c2b-1a09-4f8e-8d7c-6b5a49382716 -E hash_seed=0f1e2d3c-4b5a-4978-8695-a4b3c2d1e0f9 \
+    "$OUT" 4096
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Generates ext2fs_trailing_block.bin, a 1KiB-block image with a single group of 1024 blocks.
+# Block 0 comes before the first group, so the 1025 blocks of the image don't fill two groups.
+set -e
+
+OUT="${1:-ext2fs_trailing_block.bin}"
+
+rm -f "$OUT"
+E2FSPROGS_FAKE_TIME=1623268620 mke2fs -q -t ext2 -b 1024 -N 32 -g 1024 \
+    -U 3f1c2b4a-5d6e-4f70-8a9b-0c1d2e3f4a5b -E hash_seed=6a5b4c3d-2e1f-4a0b-9c8d-7e6f5a4b3c2d \
+    "$OUT" 1025
@@ -0,0 +1,473 @@
+use crate::BlockDevice;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+struct CachedBlock {
+    data: Vec<u8>,
+    last_used: u64,
+    /// The block was written but not pushed to the device yet
+    dirty: bool,
+}
+
+struct Cache {
+    blocks: BTreeMap<usize, CachedBlock>,
+    /// Indices of the clean and the dirty cached blocks, keyed by when they were last used
+    clean_by_use: BTreeMap<u64, usize>,
+    dirty_by_use: BTreeMap<u64, usize>,
+    clock: u64,
+}
+
+impl Cache {
+    fn new() -> Self {
+        Cache {
+            blocks: BTreeMap::new(),
+            clean_by_use: BTreeMap::new(),
+            dirty_by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn by_use(&mut self, dirty: bool) -> &mut BTreeMap<u64, usize> {
+        if dirty {
+            &mut self.dirty_by_use
+        } else {
+            &mut self.clean_by_use
+        }
+    }
+
+    /// Returns the least recently used block, only considering clean blocks if `clean_only`
+    fn lru(&self, clean_only: bool) -> Option<usize> {
+        let clean = self.clean_by_use.iter().next();
+        let dirty = self.dirty_by_use.iter().next().filter(|_| !clean_only);
+        clean
+            .into_iter()
+            .chain(dirty)
+            .min()
+            .map(|(_, &index)| index)
+    }
+
+    /// Caches `data` as the most recently used block, replacing any cached copy
+    fn insert(&mut self, index: usize, data: Vec<u8>, dirty: bool) {
+        self.remove(index);
+        self.clock += 1;
+        let last_used = self.clock;
+        self.by_use(dirty).insert(last_used, index);
+        self.blocks.insert(
+            index,
+            CachedBlock {
+                data,
+                last_used,
+                dirty,
+            },
+        );
+    }
+
+    fn remove(&mut self, index: usize) -> Option<CachedBlock> {
+        let block = self.blocks.remove(&index)?;
+        self.by_use(block.dirty).remove(&block.last_used);
+        Some(block)
+    }
+
+    /// Marks the block as the most recently used one and returns it
+    fn touch(&mut self, index: usize) -> Option<&CachedBlock> {
+        self.clock += 1;
+        let clock = self.clock;
+        let block = self.blocks.get_mut(&index)?;
+        let (dirty, last_used) = (block.dirty, block.last_used);
+        block.last_used = clock;
+
+        let by_use = self.by_use(dirty);
+        by_use.remove(&last_used);
+        by_use.insert(clock, index);
+        self.blocks.get(&index)
+    }
+
+    /// Marks the block as written back to the device
+    fn set_clean(&mut self, index: usize) {
+        if let Some(block) = self.blocks.get_mut(&index) {
+            if block.dirty {
+                block.dirty = false;
+                let last_used = block.last_used;
+                self.dirty_by_use.remove(&last_used);
+                self.clean_by_use.insert(last_used, index);
+            }
+        }
+    }
+}
+
+/// Block device adapter that keeps the most recently used blocks of the underlying device in
+/// memory. By default writes go straight through to the device and update the cached copy. In
+/// write-back mode they only reach the device when the block is evicted, on `flush`, or when the
+/// adapter is dropped.
+pub struct CachingDevice<T: BlockDevice> {
+    /// Only `None` after `into_inner` took it
+    device: Option<T>,
+    capacity: usize,
+    write_back: bool,
+    cache: RefCell<Cache>,
+}
+
+impl<T: BlockDevice> CachingDevice<T> {
+    /// Wraps `device`, caching up to `capacity` blocks
+    pub fn new(device: T, capacity: usize) -> Self {
+        CachingDevice {
+            device: Some(device),
+            capacity,
+            write_back: false,
+            cache: RefCell::new(Cache::new()),
+        }
+    }
+
+    /// Wraps `device`, caching up to `capacity` blocks and delaying writes until the blocks are
+    /// flushed
+    pub fn write_back(device: T, capacity: usize) -> Self {
+        let mut caching_device = Self::new(device, capacity);
+        caching_device.write_back = true;
+        caching_device
+    }
+
+    /// Returns a reference to the underlying device
+    pub fn inner(&self) -> &T {
+        self.device.as_ref().unwrap()
+    }
+
+    fn inner_mut(&mut self) -> &mut T {
+        self.device.as_mut().unwrap()
+    }
+
+    /// Flushes the dirty blocks, then consumes the adapter and returns the underlying device
+    pub fn into_inner(mut self) -> Result<T, T::Error> {
+        self.flush()?;
+        Ok(self.device.take().unwrap())
+    }
+
+    /// Caches a clean block read from the device. Blocks that are cached already are newer than
+    /// the device contents, so they are kept. Dirty blocks are never evicted here, since that
+    /// would need writing them back.
+    fn insert_clean(&self, index: usize, data: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut cache = self.cache.borrow_mut();
+        if cache.blocks.contains_key(&index) {
+            return;
+        }
+        if cache.blocks.len() >= self.capacity {
+            match cache.lru(true) {
+                Some(lru) => cache.remove(lru),
+                None => return,
+            };
+        }
+        cache.insert(index, data.to_vec(), false);
+    }
+
+    /// Caches a block that was just written, marking it dirty in write-back mode. The least
+    /// recently used block is evicted if needed, and written back if it is dirty.
+    fn insert_written(&mut self, index: usize, data: &[u8]) -> Result<(), T::Error> {
+        let dirty = self.write_back;
+        let evicted = {
+            let mut cache = self.cache.borrow_mut();
+            let evicted =
+                if !cache.blocks.contains_key(&index) && cache.blocks.len() >= self.capacity {
+                    cache
+                        .lru(false)
+                        .and_then(|lru| cache.remove(lru).map(|block| (lru, block)))
+                } else {
+                    None
+                };
+            cache.insert(index, data.to_vec(), dirty);
+            evicted
+        };
+
+        match evicted {
+            Some((index, block)) if block.dirty => {
+                self.inner_mut().write_blocks(index, &block.data)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn read_cached(&self, index: usize, num_blocks: usize) -> Option<Vec<u8>> {
+        let mut cache = self.cache.borrow_mut();
+        if !(index..index + num_blocks).all(|index| cache.blocks.contains_key(&index)) {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(num_blocks * self.inner().get_block_size());
+        for index in index..index + num_blocks {
+            data.extend_from_slice(&cache.touch(index).unwrap().data);
+        }
+        Some(data)
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for CachingDevice<T> {
+    type Error = T::Error;
+
+    fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, T::Error> {
+        if let Some(data) = self.read_cached(index, num_blocks) {
+            return Ok(data);
+        }
+
+        let mut data = self.inner().read_blocks(index, num_blocks)?;
+        let block_size = self.inner().get_block_size();
+        // Only complete blocks are cached, a short read is returned as is
+        for (i, block) in data.chunks_exact_mut(block_size).enumerate() {
+            // Cached blocks may hold writes the device hasn't seen yet
+            if let Some(cached) = self.cache.borrow().blocks.get(&(index + i)) {
+                block.copy_from_slice(&cached.data);
+            }
+            self.insert_clean(index + i, block);
+        }
+        Ok(data)
+    }
+
+    fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), T::Error> {
+        let block_size = self.inner().get_block_size();
+        if !self.write_back || self.capacity == 0 {
+            self.inner_mut().write_blocks(index, data)?;
+        }
+
+        for (i, block) in data.chunks(block_size).enumerate() {
+            if block.len() == block_size {
+                if self.capacity != 0 {
+                    self.insert_written(index + i, block)?;
+                }
+                continue;
+            }
+
+            // A partial block write leaves the rest of the block unknown, unless it is cached
+            let mut cache = self.cache.borrow_mut();
+            match cache.blocks.get_mut(&(index + i)) {
+                Some(cached) if cached.dirty => cached.data[..block.len()].copy_from_slice(block),
+                _ => {
+                    cache.remove(index + i);
+                    drop(cache);
+                    if self.write_back && self.capacity != 0 {
+                        self.inner_mut().write_blocks(index + i, block)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.inner().get_block_size()
+    }
+
+    /// Writes the dirty blocks back to the device, in increasing block order
+    fn flush(&mut self) -> Result<(), T::Error> {
+        let dirty: Vec<(usize, Vec<u8>)> = self
+            .cache
+            .borrow()
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.dirty)
+            .map(|(&index, block)| (index, block.data.clone()))
+            .collect();
+
+        for (index, data) in dirty {
+            self.inner_mut().write_blocks(index, &data)?;
+            self.cache.borrow_mut().set_clean(index);
+        }
+        self.inner_mut().flush()
+    }
+}
+
+impl<T: BlockDevice> Drop for CachingDevice<T> {
+    fn drop(&mut self) {
+        if self.device.is_some() {
+            // There is no way to report the error from here, call flush() to handle it
+            let _ = self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct CountingDevice {
+        data: Vec<u8>,
+        reads: Cell<usize>,
+    }
+
+    impl CountingDevice {
+        const BLOCK_SIZE: usize = 512;
+
+        fn new(num_blocks: usize) -> Self {
+            CountingDevice {
+                data: (0..num_blocks * Self::BLOCK_SIZE)
+                    .map(|i| (i / Self::BLOCK_SIZE) as u8)
+                    .collect(),
+                reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl BlockDevice for CountingDevice {
+        type Error = ();
+
+        fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, ()> {
+            self.reads.set(self.reads.get() + 1);
+            let start = index * Self::BLOCK_SIZE;
+            Ok(self.data[start..start + num_blocks * Self::BLOCK_SIZE].to_vec())
+        }
+
+        fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), ()> {
+            let start = index * Self::BLOCK_SIZE;
+            self.data[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn get_block_size(&self) -> usize {
+            Self::BLOCK_SIZE
+        }
+    }
+
+    #[test]
+    fn second_read_is_served_from_cache() {
+        let dev = CachingDevice::new(CountingDevice::new(8), 4);
+
+        let first = dev.read_blocks(2, 2).unwrap();
+        let second = dev.read_blocks(2, 2).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(dev.inner().reads.get(), 1);
+
+        // A single block out of an already cached range doesn't hit the device either
+        assert_eq!(
+            dev.read_blocks(3, 1).unwrap(),
+            vec![3; CountingDevice::BLOCK_SIZE]
+        );
+        assert_eq!(dev.inner().reads.get(), 1);
+    }
+
+    #[test]
+    fn least_recently_used_block_is_evicted() {
+        let dev = CachingDevice::new(CountingDevice::new(8), 2);
+
+        dev.read_blocks(0, 1).unwrap();
+        dev.read_blocks(1, 1).unwrap();
+        dev.read_blocks(0, 1).unwrap();
+        dev.read_blocks(2, 1).unwrap(); // Evicts block 1
+        assert_eq!(dev.inner().reads.get(), 3);
+
+        dev.read_blocks(0, 1).unwrap();
+        assert_eq!(dev.inner().reads.get(), 3);
+        dev.read_blocks(1, 1).unwrap();
+        assert_eq!(dev.inner().reads.get(), 4);
+    }
+
+    #[test]
+    fn writes_update_the_cache() {
+        let mut dev = CachingDevice::new(CountingDevice::new(8), 4);
+
+        dev.read_blocks(1, 1).unwrap();
+        dev.write_blocks(1, &[0xAA; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        assert_eq!(
+            dev.read_blocks(1, 1).unwrap(),
+            vec![0xAA; CountingDevice::BLOCK_SIZE]
+        );
+        assert_eq!(dev.inner().reads.get(), 1);
+        assert_eq!(
+            dev.into_inner().unwrap().data[CountingDevice::BLOCK_SIZE],
+            0xAA
+        );
+    }
+
+    #[test]
+    fn write_back_delays_writes_until_flush() {
+        let mut dev = CachingDevice::write_back(CountingDevice::new(8), 4);
+
+        dev.write_blocks(5, &[0xAA; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        dev.write_blocks(2, &[0xBB; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        assert_eq!(dev.inner().data[5 * CountingDevice::BLOCK_SIZE], 5);
+        assert_eq!(dev.inner().data[2 * CountingDevice::BLOCK_SIZE], 2);
+
+        // Reads see the unflushed data, even when only part of the range is cached
+        let data = dev.read_blocks(1, 2).unwrap();
+        assert_eq!(data[0], 1);
+        assert_eq!(data[CountingDevice::BLOCK_SIZE], 0xBB);
+        assert_eq!(dev.read_blocks(2, 1).unwrap()[0], 0xBB);
+
+        dev.flush().unwrap();
+        assert_eq!(dev.inner().data[5 * CountingDevice::BLOCK_SIZE], 0xAA);
+        assert_eq!(dev.inner().data[2 * CountingDevice::BLOCK_SIZE], 0xBB);
+    }
+
+    #[test]
+    fn evicted_dirty_blocks_are_written_back() {
+        let mut dev = CachingDevice::write_back(CountingDevice::new(8), 1);
+
+        dev.write_blocks(1, &[0xAA; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        // Reads don't evict dirty blocks, the block read is just not cached
+        assert_eq!(dev.read_blocks(3, 1).unwrap()[0], 3);
+        assert_eq!(dev.inner().data[CountingDevice::BLOCK_SIZE], 1);
+
+        // Writing another block evicts the dirty one
+        dev.write_blocks(2, &[0xBB; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        assert_eq!(dev.inner().data[CountingDevice::BLOCK_SIZE], 0xAA);
+        assert_eq!(dev.inner().data[2 * CountingDevice::BLOCK_SIZE], 2);
+
+        let dev = dev.into_inner().unwrap();
+        assert_eq!(dev.data[2 * CountingDevice::BLOCK_SIZE], 0xBB);
+    }
+
+    #[test]
+    fn flushed_blocks_can_be_evicted_by_reads() {
+        let mut dev = CachingDevice::write_back(CountingDevice::new(8), 1);
+
+        dev.write_blocks(1, &[0xAA; CountingDevice::BLOCK_SIZE])
+            .unwrap();
+        dev.read_blocks(3, 1).unwrap();
+        dev.read_blocks(3, 1).unwrap();
+        assert_eq!(dev.inner().reads.get(), 2);
+
+        // Once clean, the written block makes room for the one read
+        dev.flush().unwrap();
+        dev.read_blocks(3, 1).unwrap();
+        dev.read_blocks(3, 1).unwrap();
+        assert_eq!(dev.inner().reads.get(), 3);
+        assert_eq!(dev.read_blocks(1, 1).unwrap()[0], 0xAA);
+        assert_eq!(dev.inner().reads.get(), 4);
+    }
+
+    #[test]
+    fn dirty_blocks_are_flushed_on_drop() {
+        struct SharedDevice(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+        impl BlockDevice for SharedDevice {
+            type Error = ();
+
+            fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, ()> {
+                Ok(self.0.borrow()[index * 512..(index + num_blocks) * 512].to_vec())
+            }
+
+            fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), ()> {
+                self.0.borrow_mut()[index * 512..index * 512 + data.len()].copy_from_slice(data);
+                Ok(())
+            }
+
+            fn get_block_size(&self) -> usize {
+                512
+            }
+        }
+
+        let data = std::rc::Rc::new(std::cell::RefCell::new(vec![0; 4 * 512]));
+        let mut dev = CachingDevice::write_back(SharedDevice(data.clone()), 4);
+        dev.write_blocks(3, &[0xAA; 512]).unwrap();
+        assert_eq!(data.borrow()[3 * 512], 0);
+
+        drop(dev);
+        assert_eq!(data.borrow()[3 * 512], 0xAA);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{BlockDevice, Error, Ext2Fs, FileType};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+/// Size of the fixed part of a directory entry: inode, rec_len, name_len and file_type
+const HEADER_SIZE: usize = 8;
+
+/// Record length stored for entries spanning a whole 64KiB block, since 65536 doesn't fit in
+/// 16 bits
+const MAX_REC_LEN: usize = 65535;
+
+/// Decodes the record length of an entry in a block of `block_size` bytes, like the kernel's
+/// `ext4_rec_len_from_disk`. Besides `MAX_REC_LEN`, some kernels store a whole 64KiB block as 0.
+fn rec_len_from_disk(raw: u16, block_size: usize) -> usize {
+    let len = raw as usize;
+    if block_size < 1 << 16 {
+        return len;
+    }
+    if len == MAX_REC_LEN || len == 0 {
+        return block_size;
+    }
+    len
+}
+
+/// Encodes a record length, the reverse of `rec_len_from_disk`
+fn rec_len_to_disk(len: usize) -> u16 {
+    if len == 1 << 16 {
+        return MAX_REC_LEN as u16;
+    }
+    len as u16
+}
+
+/// Entry of a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub inode: u32,
+    pub name: String,
+    /// Type recorded in the entry. Filesystems without the filetype feature don't record it,
+    /// and report `FileType::Unknown(0)` for every entry; the inode still has the type.
+    pub file_type: FileType,
+}
+
+/// Iterator over the entries of a directory, returned by `Ext2Fs::read_dir`. Directory blocks
+/// are read from the device as the iteration reaches them.
+pub struct DirIterator<'a, T: BlockDevice> {
+    fs: &'a Ext2Fs<T>,
+    blocks: Vec<u32>,
+    next_block: usize,
+    data: Vec<u8>,
+    offset: usize,
+    has_file_type: bool,
+    strict: bool,
+}
+
+impl<'a, T: BlockDevice> DirIterator<'a, T> {
+    pub(crate) fn new(fs: &'a Ext2Fs<T>, blocks: Vec<u32>, has_file_type: bool) -> Self {
+        DirIterator {
+            fs,
+            blocks,
+            next_block: 0,
+            data: vec![],
+            offset: 0,
+            has_file_type,
+            strict: false,
+        }
+    }
+
+    /// Makes the iterator return `Error::UnknownFileType` for entries recording a file type
+    /// that isn't known, instead of `FileType::Unknown`. Iteration continues after them.
+    /// Entries of filesystems that don't record file types are still returned.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Parses the entry at the current offset and moves past it. Returns `None` for unused
+    /// entries.
+    fn parse_entry(&mut self) -> Result<Option<DirEntry>, Error> {
+        let entry = &self.data[self.offset..];
+        if entry.len() < HEADER_SIZE {
+            return Err(Error::CorruptedDirectory);
+        }
+
+        let inode = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+        let rec_len = rec_len_from_disk(
+            u16::from_le_bytes(entry[4..6].try_into().unwrap()),
+            self.data.len(),
+        );
+        // Without the filetype feature, the name length is a 16 bit field
+        let (name_len, file_type) = if self.has_file_type {
+            (entry[6] as usize, FileType::from_dir_entry(entry[7]))
+        } else {
+            (
+                u16::from_le_bytes(entry[6..8].try_into().unwrap()) as usize,
+                FileType::Unknown(0),
+            )
+        };
+
+        // Entries are 4 byte aligned, hold their name and never cross a block boundary
+        if rec_len % 4 != 0 || rec_len < HEADER_SIZE + name_len || rec_len > entry.len() {
+            return Err(Error::CorruptedDirectory);
+        }
+        self.offset += rec_len;
+
+        if inode == 0 {
+            return Ok(None);
+        }
+
+        let name = &entry[HEADER_SIZE..HEADER_SIZE + name_len];
+        Ok(Some(DirEntry {
+            inode,
+            name: String::from_utf8_lossy(name).into_owned(),
+            file_type,
+        }))
+    }
+}
+
+impl<'a, T: BlockDevice> Iterator for DirIterator<'a, T> {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.offset >= self.data.len() {
+                let block = *self.blocks.get(self.next_block)?;
+                if block == 0 {
+                    // Directories can't have holes
+                    self.blocks.clear();
+                    return Some(Err(Error::CorruptedDirectory));
+                }
+
+                self.data = match self.fs.read_fs_blocks(block as usize, 1) {
+                    Ok(data) => data,
+                    Err(error) => {
+                        self.blocks.clear();
+                        return Some(Err(error));
+                    }
+                };
+                self.offset = 0;
+                self.next_block += 1;
+            }
+
+            match self.parse_entry() {
+                Ok(Some(DirEntry {
+                    file_type: FileType::Unknown(file_type),
+                    ..
+                })) if self.strict && self.has_file_type => {
+                    return Some(Err(Error::UnknownFileType(file_type)))
+                }
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(error) => {
+                    // The rest of the directory can't be trusted, stop iterating
+                    self.blocks.clear();
+                    self.data.clear();
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the space taken by an entry with a name of `name_len` bytes
+fn entry_len(name_len: usize) -> usize {
+    HEADER_SIZE + name_len.div_ceil(4) * 4
+}
+
+/// Writes an entry spanning `rec_len` bytes at the start of `out`. Without the filetype feature,
+/// `file_type` is `None` and the upper byte of the name length is stored instead.
+fn write_entry(out: &mut [u8], inode: u32, rec_len: usize, name: &[u8], file_type: Option<u8>) {
+    out[0..4].copy_from_slice(&inode.to_le_bytes());
+    out[4..6].copy_from_slice(&rec_len_to_disk(rec_len).to_le_bytes());
+    out[6] = name.len() as u8;
+    out[7] = file_type.unwrap_or(0);
+    out[HEADER_SIZE..HEADER_SIZE + name.len()].copy_from_slice(name);
+    for byte in &mut out[HEADER_SIZE + name.len()..entry_len(name.len())] {
+        *byte = 0;
+    }
+}
+
+/// Inserts an entry into a directory block, reusing an unused entry or the slack at the end of
+/// an existing one. Returns false if the block doesn't have enough room.
+pub(crate) fn insert_entry(
+    block: &mut [u8],
+    inode: u32,
+    name: &[u8],
+    file_type: Option<u8>,
+) -> Result<bool, Error> {
+    let needed = entry_len(name.len());
+
+    let mut offset = 0;
+    while offset < block.len() {
+        let entry = &block[offset..];
+        if entry.len() < HEADER_SIZE {
+            return Err(Error::CorruptedDirectory);
+        }
+
+        let entry_inode = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+        let rec_len = rec_len_from_disk(
+            u16::from_le_bytes(entry[4..6].try_into().unwrap()),
+            block.len(),
+        );
+        // Names are at most 255 bytes, so this is right even without the filetype feature
+        let name_len = entry[6] as usize;
+        if rec_len % 4 != 0 || rec_len < HEADER_SIZE + name_len || rec_len > entry.len() {
+            return Err(Error::CorruptedDirectory);
+        }
+
+        let used = if entry_inode == 0 {
+            0
+        } else {
+            entry_len(name_len)
+        };
+        if rec_len - used >= needed {
+            if used != 0 {
+                // Shrink the existing entry and place the new one in its slack
+                block[offset + 4..offset + 6].copy_from_slice(&rec_len_to_disk(used).to_le_bytes());
+            }
+            write_entry(
+                &mut block[offset + used..],
+                inode,
+                rec_len - used,
+                name,
+                file_type,
+            );
+            return Ok(true);
+        }
+        offset += rec_len;
+    }
+    Ok(false)
+}
+
+/// Fills a new directory block with a single entry spanning the whole block
+pub(crate) fn init_block(block: &mut [u8], inode: u32, name: &[u8], file_type: Option<u8>) {
+    let rec_len = block.len();
+    write_entry(block, inode, rec_len, name, file_type);
+}
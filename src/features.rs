@@ -0,0 +1,209 @@
+//! Feature flags stored in the superblock. Each of the three feature words is exposed as a set
+//! of flags; bits that aren't known to this crate are retained so that they can be reported.
+
+/// Declares a set of feature flags over a `u32` feature word
+macro_rules! feature_flags {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$flag_meta:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        pub struct $name(u32);
+
+        impl $name {
+            $(
+                $(#[$flag_meta])*
+                pub const $flag: $name = $name($value);
+            )*
+
+            /// Returns a set without any feature
+            pub const fn empty() -> Self {
+                $name(0)
+            }
+
+            /// Returns the set of all the features known to this crate
+            pub const fn all() -> Self {
+                $name(0 $(| $value)*)
+            }
+
+            /// Builds a set from a raw feature word. Unknown bits are kept.
+            pub const fn from_bits(bits: u32) -> Self {
+                $name(bits)
+            }
+
+            /// Returns the raw feature word
+            pub const fn bits(self) -> u32 {
+                self.0
+            }
+
+            /// Returns true if no feature is set
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Returns true if all the features in `other` are set
+            pub const fn contains(self, other: Self) -> bool {
+                (self.0 & other.0) == other.0
+            }
+
+            /// Returns true if any of the features in `other` is set
+            pub const fn intersects(self, other: Self) -> bool {
+                (self.0 & other.0) != 0
+            }
+
+            /// Returns the bits that don't correspond to any feature known to this crate
+            pub const fn unknown(self) -> Self {
+                $name(self.0 & !Self::all().0)
+            }
+        }
+
+        impl core::ops::BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, other: Self) -> Self {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl core::ops::BitAnd for $name {
+            type Output = Self;
+
+            fn bitand(self, other: Self) -> Self {
+                $name(self.0 & other.0)
+            }
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, "{}(", stringify!($name))?;
+                let mut separator = "";
+                $(
+                    if self.contains(Self::$flag) {
+                        write!(f, "{}{}", separator, stringify!($flag))?;
+                        separator = " | ";
+                    }
+                )*
+                let unknown = self.unknown();
+                if !unknown.is_empty() {
+                    write!(f, "{}{:#x}", separator, unknown.0)?;
+                }
+                write!(f, ")")
+            }
+        }
+    };
+}
+
+feature_flags! {
+    /// Compatible features, `s_feature_compat`. An implementation can use the filesystem even if
+    /// it doesn't understand them.
+    pub struct CompatFeatures {
+        /// Directories preallocate blocks
+        const DIR_PREALLOC = 0x0001;
+        /// AFS server inodes exist
+        const IMAGIC_INODES = 0x0002;
+        /// The filesystem has a journal (ext3)
+        const HAS_JOURNAL = 0x0004;
+        /// Inodes can have extended attributes
+        const EXT_ATTR = 0x0008;
+        /// Blocks are reserved for growing the group descriptor table
+        const RESIZE_INODE = 0x0010;
+        /// Directories can be indexed with hashed b-trees
+        const DIR_INDEX = 0x0020;
+        /// Uninitialized block groups (lazy_bg)
+        const LAZY_BG = 0x0040;
+        /// Exclude bitmaps for snapshots
+        const EXCLUDE_BITMAP = 0x0100;
+        /// Only two superblock backups, placed in `s_backup_bgs`
+        const SPARSE_SUPER2 = 0x0200;
+        /// The journal supports fast commits
+        const FAST_COMMIT = 0x0400;
+        /// Inode numbers and UUID never change
+        const STABLE_INODES = 0x0800;
+        /// Orphan inodes are tracked in a dedicated file
+        const ORPHAN_FILE = 0x1000;
+    }
+}
+
+feature_flags! {
+    /// Incompatible features, `s_feature_incompat`. An implementation must refuse to mount the
+    /// filesystem if it doesn't understand any of them.
+    pub struct IncompatFeatures {
+        /// Compressed files
+        const COMPRESSION = 0x0001;
+        /// Directory entries record the file type
+        const FILETYPE = 0x0002;
+        /// The journal needs to be replayed (ext3)
+        const RECOVER = 0x0004;
+        /// The filesystem is an external journal device
+        const JOURNAL_DEV = 0x0008;
+        /// Meta block groups
+        const META_BG = 0x0010;
+        /// Files are mapped with extent trees
+        const EXTENTS = 0x0040;
+        /// Block numbers can be larger than 32 bits
+        const BIT64 = 0x0080;
+        /// Multiple mount protection
+        const MMP = 0x0100;
+        /// Flexible block groups
+        const FLEX_BG = 0x0200;
+        /// Extended attribute values can be stored in separate inodes
+        const EA_INODE = 0x0400;
+        /// Directory entries can carry extra data
+        const DIRDATA = 0x1000;
+        /// The checksum seed is stored in the superblock
+        const CSUM_SEED = 0x2000;
+        /// Directories can be larger than 2GB, with 3-level hashed b-trees
+        const LARGEDIR = 0x4000;
+        /// Small files are stored inside the inode
+        const INLINE_DATA = 0x8000;
+        /// Encrypted inodes
+        const ENCRYPT = 0x10000;
+        /// Case-insensitive file names
+        const CASEFOLD = 0x20000;
+    }
+}
+
+feature_flags! {
+    /// Read-only compatible features, `s_feature_ro_compat`. An implementation that doesn't
+    /// understand them can still mount the filesystem, but only for reading.
+    pub struct RoCompatFeatures {
+        /// Superblock backups are only kept in some block groups
+        const SPARSE_SUPER = 0x0001;
+        /// Files can be larger than 2GB
+        const LARGE_FILE = 0x0002;
+        /// Binary tree sorted directories
+        const BTREE_DIR = 0x0004;
+        /// Files can be larger than 2TB
+        const HUGE_FILE = 0x0008;
+        /// Group descriptors are protected by checksums
+        const GDT_CSUM = 0x0010;
+        /// Directories can have more than 65000 subdirectories
+        const DIR_NLINK = 0x0020;
+        /// Inodes reserve extra space beyond the original structure
+        const EXTRA_ISIZE = 0x0040;
+        /// The filesystem has a snapshot
+        const HAS_SNAPSHOT = 0x0080;
+        /// Quota is tracked in hidden inodes
+        const QUOTA = 0x0100;
+        /// Blocks are allocated in clusters
+        const BIGALLOC = 0x0200;
+        /// Metadata is protected by checksums
+        const METADATA_CSUM = 0x0400;
+        /// Replicas
+        const REPLICA = 0x0800;
+        /// The filesystem must only be mounted read-only
+        const READONLY = 0x1000;
+        /// Project quota is tracked
+        const PROJECT = 0x2000;
+        /// Files can be protected with fs-verity
+        const VERITY = 0x8000;
+        /// Orphan inodes are recorded in the orphan file
+        const ORPHAN_PRESENT = 0x10000;
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{BlockDevice, Error, Ext2Fs, Inode};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Converts an error of the filesystem into an I/O error of the closest kind. The error itself
+/// is kept as the payload, so that it can be recovered with `downcast`.
+#[cfg(feature = "std")]
+fn io_error(error: Error) -> std::io::Error {
+    use std::io::ErrorKind;
+
+    let kind = match error {
+        Error::NotFound(_) => ErrorKind::NotFound,
+        Error::AlreadyExists(_) => ErrorKind::AlreadyExists,
+        Error::InvalidArgument | Error::InvalidPath => ErrorKind::InvalidInput,
+        Error::NoSpaceLeft => ErrorKind::StorageFull,
+        Error::ReadOnly => ErrorKind::ReadOnlyFilesystem,
+        Error::IsADirectory => ErrorKind::IsADirectory,
+        Error::NotADirectory => ErrorKind::NotADirectory,
+        Error::ShortRead => ErrorKind::UnexpectedEof,
+        Error::InvalidInode
+        | Error::CorruptedInode
+        | Error::CorruptedDirectory
+        | Error::CorruptedGroupDescriptors => ErrorKind::InvalidData,
+        _ => ErrorKind::Other,
+    };
+    std::io::Error::new(kind, error)
+}
+
+/// Position in an open file, with the block under it kept in memory so that small sequential
+/// reads don't hit the device every time
+struct Cursor {
+    inode: Inode,
+    position: u64,
+    /// Last indirect block of data block pointers read, see `Ext2Fs::map_block`
+    leaf: Option<(usize, Vec<u8>)>,
+    /// Logical block number and contents of the last block read
+    buffer: Option<(usize, Vec<u8>)>,
+}
+
+impl Cursor {
+    fn new(inode: Inode) -> Self {
+        Cursor {
+            inode,
+            position: 0,
+            leaf: None,
+            buffer: None,
+        }
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    fn read<T: BlockDevice>(&mut self, fs: &Ext2Fs<T>, buf: &mut [u8]) -> Result<usize, Error> {
+        let size = self.inode.size();
+        // Files claiming more than their block pointers address can't be read at all
+        fs.file_blocks(&self.inode)?;
+        if buf.is_empty() || self.position >= size {
+            return Ok(0);
+        }
+
+        let block_size = fs.block_size() as u64;
+        let logical = (self.position / block_size) as usize;
+        let offset = (self.position % block_size) as usize;
+
+        let cached = matches!(&self.buffer, Some((block, _)) if *block == logical);
+        if !cached {
+            let physical = fs.map_block(&self.inode, logical, &mut self.leaf)?;
+            let data = if physical == 0 {
+                vec![0; fs.block_size()]
+            } else {
+                fs.read_fs_blocks(physical as usize, 1)?
+            };
+            self.buffer = Some((logical, data));
+        }
+
+        let data = &self.buffer.as_ref().unwrap().1;
+        let len = buf
+            .len()
+            .min(data.len() - offset)
+            .min((size - self.position) as usize);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    #[cfg(feature = "std")]
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::End(offset) => (self.inode.size(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Handle to an open file, returned by `Ext2Fs::open`. With the `std` feature it implements `Read`
+/// and `Seek`.
+pub struct File<'a, T: BlockDevice> {
+    fs: &'a Ext2Fs<T>,
+    cursor: Cursor,
+}
+
+impl<'a, T: BlockDevice> File<'a, T> {
+    pub(crate) fn new(fs: &'a Ext2Fs<T>, inode: Inode) -> Self {
+        File {
+            fs,
+            cursor: Cursor::new(inode),
+        }
+    }
+
+    /// Returns the inode of the file
+    pub fn inode(&self) -> &Inode {
+        &self.cursor.inode
+    }
+
+    /// Returns the size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.cursor.inode.size()
+    }
+
+    /// Reads the whole contents of the file, regardless of the current position
+    pub fn read_all(&self) -> Result<Vec<u8>, Error> {
+        self.fs.read_file(&self.cursor.inode)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: BlockDevice> Read for File<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.cursor.read(self.fs, buf).map_err(io_error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: BlockDevice> Seek for File<'a, T> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        self.cursor.seek(position)
+    }
+}
+
+/// Handle to a file opened for writing, returned by `Ext2Fs::open_mut`. With the `std` feature it
+/// implements `Read`, `Seek` and `Write`.
+pub struct FileMut<'a, T: BlockDevice> {
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    fs: &'a mut Ext2Fs<T>,
+    cursor: Cursor,
+}
+
+impl<'a, T: BlockDevice> FileMut<'a, T> {
+    pub(crate) fn new(fs: &'a mut Ext2Fs<T>, inode: Inode) -> Self {
+        FileMut {
+            fs,
+            cursor: Cursor::new(inode),
+        }
+    }
+
+    /// Returns the inode of the file
+    pub fn inode(&self) -> &Inode {
+        &self.cursor.inode
+    }
+
+    /// Returns the size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.cursor.inode.size()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: BlockDevice> Read for FileMut<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.cursor.read(self.fs, buf).map_err(io_error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: BlockDevice> Seek for FileMut<'a, T> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        self.cursor.seek(position)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: BlockDevice> Write for FileMut<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let cursor = &mut self.cursor;
+        let (written, result) = self
+            .fs
+            .write_file_partial(&mut cursor.inode, cursor.position, buf);
+        // Bytes that made it to disk before a failure are reported as a short write, the error
+        // only when there are none
+        if written == 0 {
+            result.map_err(io_error)?;
+        }
+        cursor.position += written as u64;
+
+        // The written range may have been buffered, or may have allocated blocks
+        cursor.leaf = None;
+        cursor.buffer = None;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Pushes out what a write-back cache may be holding
+        self.fs.flush().map_err(io_error)
+    }
+}
@@ -0,0 +1,275 @@
+use crate::on_disk::OnDisk;
+
+on_disk_struct! {
+    /// Inode, as stored in the inode table. Only the fields of the original 128 byte
+    /// structure are described, filesystems may use a larger inode size.
+    #[allow(dead_code)]
+    #[derive(Debug, Clone)]
+    pub(crate) struct Ext2Inode {
+        i_mode: u16,                         /* File mode */
+        i_uid: u16,                          /* Low 16 bits of Owner Uid */
+        i_size: u32,                         /* Size in bytes */
+        i_atime: u32,                        /* Access time */
+        i_ctime: u32,                        /* Creation time */
+        i_mtime: u32,                        /* Modification time */
+        i_dtime: u32,                        /* Deletion Time */
+        i_gid: u16,                          /* Low 16 bits of Group Id */
+        i_links_count: u16,                  /* Links count */
+        i_blocks: u32,                       /* Blocks count */
+        i_flags: u32,                        /* File flags */
+        l_i_reserved1: u32,                  /* OS dependent 1 */
+        i_block: [u32; Ext2Inode::N_BLOCKS], /* Pointers to blocks */
+        i_generation: u32,                   /* File version (for NFS) */
+        i_file_acl: u32,                     /* File ACL */
+        i_dir_acl: u32,                      /* Directory ACL / high 32 bits of size */
+        i_faddr: u32,                        /* Fragment address */
+        /*
+         * OS dependent 2, laid out as on Linux
+         */
+        l_i_frag: u8,  /* Fragment number */
+        l_i_fsize: u8, /* Fragment size */
+        i_pad1: u16,
+        l_i_uid_high: u16, /* these 2 fields    */
+        l_i_gid_high: u16, /* were reserved2[0] */
+        l_i_reserved2: u32,
+    }
+}
+
+impl Ext2Inode {
+    /// Number of direct block pointers in `i_block`
+    pub(crate) const NDIR_BLOCKS: usize = 12;
+
+    /// Number of block pointers in `i_block`: 12 direct, plus single, double and triple indirect
+    pub(crate) const N_BLOCKS: usize = 15;
+}
+
+/// Type of a file, as encoded in the mode of its inode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Symlink,
+    /// Type not known to this crate, with the raw value it was decoded from: the file type of a
+    /// directory entry, or the upper 4 bits of the mode of an inode
+    Unknown(u8),
+}
+
+impl FileType {
+    const S_IFMT: u16 = 0xF000;
+    const S_IFSOCK: u16 = 0xC000;
+    const S_IFLNK: u16 = 0xA000;
+    const S_IFREG: u16 = 0x8000;
+    const S_IFBLK: u16 = 0x6000;
+    const S_IFDIR: u16 = 0x4000;
+    const S_IFCHR: u16 = 0x2000;
+    const S_IFIFO: u16 = 0x1000;
+
+    fn from_mode(mode: u16) -> Self {
+        match mode & Self::S_IFMT {
+            Self::S_IFSOCK => FileType::Socket,
+            Self::S_IFLNK => FileType::Symlink,
+            Self::S_IFREG => FileType::Regular,
+            Self::S_IFBLK => FileType::BlockDevice,
+            Self::S_IFDIR => FileType::Directory,
+            Self::S_IFCHR => FileType::CharacterDevice,
+            Self::S_IFIFO => FileType::Fifo,
+            other => FileType::Unknown((other >> 12) as u8),
+        }
+    }
+
+    fn to_mode(self) -> u16 {
+        match self {
+            FileType::Socket => Self::S_IFSOCK,
+            FileType::Symlink => Self::S_IFLNK,
+            FileType::Regular => Self::S_IFREG,
+            FileType::BlockDevice => Self::S_IFBLK,
+            FileType::Directory => Self::S_IFDIR,
+            FileType::CharacterDevice => Self::S_IFCHR,
+            FileType::Fifo => Self::S_IFIFO,
+            FileType::Unknown(kind) => ((kind as u16) << 12) & Self::S_IFMT,
+        }
+    }
+
+    /// Decodes the file type stored in directory entries
+    pub(crate) fn from_dir_entry(file_type: u8) -> Self {
+        match file_type {
+            1 => FileType::Regular,
+            2 => FileType::Directory,
+            3 => FileType::CharacterDevice,
+            4 => FileType::BlockDevice,
+            5 => FileType::Fifo,
+            6 => FileType::Socket,
+            7 => FileType::Symlink,
+            other => FileType::Unknown(other),
+        }
+    }
+
+    /// Encodes the file type as stored in directory entries
+    pub(crate) fn to_dir_entry(self) -> u8 {
+        match self {
+            FileType::Regular => 1,
+            FileType::Directory => 2,
+            FileType::CharacterDevice => 3,
+            FileType::BlockDevice => 4,
+            FileType::Fifo => 5,
+            FileType::Socket => 6,
+            FileType::Symlink => 7,
+            FileType::Unknown(file_type) => file_type,
+        }
+    }
+}
+
+/// Metadata of a file, read from its inode
+#[derive(Debug, Clone)]
+pub struct Inode {
+    number: u32,
+    raw: Ext2Inode,
+}
+
+impl Inode {
+    pub(crate) fn new(number: u32, raw: Ext2Inode) -> Self {
+        Inode { number, raw }
+    }
+
+    /// Builds the inode of a new file with a single link, created at `time`
+    pub(crate) fn create(number: u32, file_type: FileType, permissions: u16, time: u32) -> Self {
+        let mut raw = Ext2Inode::parse(&[0; Ext2Inode::SIZE]);
+        raw.i_mode = file_type.to_mode() | (permissions & !FileType::S_IFMT);
+        raw.i_links_count = 1;
+        raw.i_atime = time;
+        raw.i_ctime = time;
+        raw.i_mtime = time;
+        Inode { number, raw }
+    }
+
+    pub(crate) fn raw(&self) -> &Ext2Inode {
+        &self.raw
+    }
+
+    /// Sets the size of the file in bytes. See `size` for how it is stored.
+    pub(crate) fn set_size(&mut self, size: u64) {
+        self.raw.i_size = size as u32;
+        if self.file_type() == FileType::Regular {
+            self.raw.i_dir_acl = (size >> 32) as u32;
+        }
+    }
+
+    pub(crate) fn set_block_pointer(&mut self, index: usize, block: u32) {
+        self.raw.i_block[index] = block;
+    }
+
+    /// Accounts for `count` more 512 byte sectors allocated to the file
+    pub(crate) fn add_sectors(&mut self, count: u32) {
+        self.raw.i_blocks += count;
+    }
+
+    pub(crate) fn set_flags(&mut self, flags: u32) {
+        self.raw.i_flags = flags;
+    }
+
+    /// Records a modification of the file contents at `time`
+    pub(crate) fn touch(&mut self, time: u32) {
+        self.raw.i_mtime = time;
+        self.raw.i_ctime = time;
+    }
+
+    /// Returns the inode number
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Returns the raw mode, containing both the file type and the permissions
+    pub fn mode(&self) -> u16 {
+        self.raw.i_mode
+    }
+
+    /// Returns the permission bits of the mode, including setuid, setgid and sticky
+    pub fn permissions(&self) -> u16 {
+        self.raw.i_mode & !FileType::S_IFMT
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.raw.i_mode)
+    }
+
+    /// Returns the size of the file in bytes. Regular files store the upper 32 bits of the size
+    /// in what used to be `i_dir_acl`.
+    pub fn size(&self) -> u64 {
+        let size = self.raw.i_size as u64;
+        if self.file_type() == FileType::Regular {
+            size | ((self.raw.i_dir_acl as u64) << 32)
+        } else {
+            size
+        }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.raw.i_uid as u32 | ((self.raw.l_i_uid_high as u32) << 16)
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.raw.i_gid as u32 | ((self.raw.l_i_gid_high as u32) << 16)
+    }
+
+    pub fn links_count(&self) -> u16 {
+        self.raw.i_links_count
+    }
+
+    /// Returns the last access time, in seconds since the Unix epoch
+    pub fn atime(&self) -> u32 {
+        self.raw.i_atime
+    }
+
+    /// Returns the last inode change time, in seconds since the Unix epoch
+    pub fn ctime(&self) -> u32 {
+        self.raw.i_ctime
+    }
+
+    /// Returns the last modification time, in seconds since the Unix epoch
+    pub fn mtime(&self) -> u32 {
+        self.raw.i_mtime
+    }
+
+    /// Returns the deletion time, in seconds since the Unix epoch. It is 0 for live inodes.
+    pub fn dtime(&self) -> u32 {
+        self.raw.i_dtime
+    }
+
+    /// Returns the number of 512 byte sectors allocated to the file, including indirect blocks
+    pub fn sector_count(&self) -> u32 {
+        self.raw.i_blocks
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.raw.i_flags
+    }
+
+    /// Returns true if the target of the symlink is stored in the inode itself. Like the kernel
+    /// does, a symlink is considered fast if it has no blocks besides an extended attribute
+    /// block.
+    pub(crate) fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let xattr_sectors = if self.raw.i_file_acl != 0 {
+            (block_size / 512) as u32
+        } else {
+            0
+        };
+        self.file_type() == FileType::Symlink && self.raw.i_blocks == xattr_sectors
+    }
+
+    /// Returns the `i_block` array as raw bytes, where fast symlinks store their target
+    pub(crate) fn inline_data(&self) -> [u8; Ext2Inode::N_BLOCKS * 4] {
+        let mut data = [0; Ext2Inode::N_BLOCKS * 4];
+        self.raw.i_block.serialize(&mut data);
+        data
+    }
+
+    /// Returns the raw block pointers: 12 direct blocks followed by the single, double and
+    /// triple indirect blocks
+    pub fn block_pointers(&self) -> &[u32; Ext2Inode::N_BLOCKS] {
+        &self.raw.i_block
+    }
+}
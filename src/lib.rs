@@ -1,253 +1,3355 @@
-use num::Integer;
-
-#[repr(C)]
-struct Ext2SuperBlock {
-    s_inodes_count: u32,      /* Inodes count */
-    s_blocks_count: u32,      /* Blocks count */
-    s_r_blocks_count: u32,    /* Reserved blocks count */
-    s_free_blocks_count: u32, /* Free blocks count */
-    s_free_inodes_count: u32, /* Free inodes count */
-    s_first_data_block: u32,  /* First Data Block */
-    s_log_block_size: i32,    /* Block size */
-    s_log_frag_size: u32,     /* Fragment size */
-    s_blocks_per_group: u32,  /* # Blocks per group */
-    s_frags_per_group: u32,   /* # Fragments per group */
-    s_inodes_per_group: u32,  /* # Inodes per group */
-    s_mtime: u32,             /* Mount time */
-    s_wtime: u32,             /* Write time */
-    s_mnt_count: u16,         /* Mount count */
-    s_max_mnt_count: u16,     /* Maximal mount count */
-    s_magic: u16,             /* Magic signature */
-    s_state: u16,             /* File system state */
-    s_errors: u16,            /* Behaviour when detecting errors */
-    s_minor_rev_level: u16,   /* minor revision level */
-    s_lastcheck: u32,         /* time of last check */
-    s_checkinterval: u32,     /* max. time between checks */
-    s_creator_os: u32,        /* OS */
-    s_rev_level: u32,         /* Revision level */
-    s_def_resuid: u16,        /* Default uid for reserved blocks */
-    s_def_resgid: u16,        /* Default gid for reserved blocks */
-    /*
-     * These fields are for EXT2_DYNAMIC_REV superblocks only.
-     *
-     * Note: the difference between the compatible feature set and
-     * the incompatible feature set is that if there is a bit set
-     * in the incompatible feature set that the kernel doesn't
-     * know about, it should refuse to mount the filesystem.
-     *
-     * e2fsck's requirements are more strict; if it doesn't know
-     * about a feature in either the compatible or incompatible
-     * feature set, it must abort and not try to meddle with
-     * things it doesn't understand...
-     */
-    s_first_ino: u32,              /* First non-reserved inode */
-    s_inode_size: u16,             /* size of inode structure */
-    s_block_group_nr: u16,         /* block group # of this superblock */
-    s_feature_compat: u32,         /* compatible feature set */
-    s_feature_incompat: u32,       /* incompatible feature set */
-    s_feature_ro_compat: u32,      /* readonly-compatible feature set */
-    s_uuid: [u8; 16],              /* 128-bit uuid for volume */
-    s_volume_name: [char; 16],     /* volume name */
-    s_last_mounted: [char; 64],    /* directory where last mounted */
-    s_algorithm_usage_bitmap: u32, /* For compression */
-    /*
-     * Performance hints.  Directory preallocation should only
-     * happen if the EXT2_COMPAT_PREALLOC flag is on.
-     */
-    s_prealloc_blocks: u8,     /* Nr of blocks to try to preallocate*/
-    s_prealloc_dir_blocks: u8, /* Nr to preallocate for dirs */
-    s_padding1: u16,
-    /*
-     * Journaling support valid if EXT3_FEATURE_COMPAT_HAS_JOURNAL set.
-     */
-    s_journal_uuid: [u8; 16], /* uuid of journal superblock */
-    s_journal_inum: u32,      /* inode number of journal file */
-    s_journal_dev: u32,       /* device number of journal file */
-    s_last_orphan: u32,       /* start of list of inodes to delete */
-    s_hash_seed: [u32; 4],    /* HTREE hash seed */
-    s_def_hash_version: u8,   /* Default hash version to use */
-    s_reserved_char_pad: u8,
-    s_reserved_word_pad: u16,
-    s_default_mount_opts: u32,
-    s_first_meta_bg: u32,   /* First metablock block group */
-    s_reserved: [u32; 190], /* Padding to the end of the block */
-}
-
-#[derive(Default)]
-struct Ext2GroupDescriptor {
-    bg_block_bitmap: u32,
-    bg_inode_bitmap: u32,
-    bg_inode_table: u32,
-    bg_free_blocks_count: u16,
-    bg_free_inodes_count: u16,
-    bg_used_dirs_count: u16,
-    bg_flags: u16,
-    bg_reserved: u32,
-    bg_itable_unused: u16,
-    bg_checksum: u16,
-}
-
-/// Trait for a block device. It reads/writes in chunks given by the block size
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+#[macro_use]
+mod on_disk;
+
+mod caching_device;
+mod dir;
+mod features;
+mod file;
+mod inode;
+mod partition_device;
+mod write;
+
+pub use caching_device::CachingDevice;
+pub use dir::{DirEntry, DirIterator};
+pub use features::{CompatFeatures, IncompatFeatures, RoCompatFeatures};
+pub use file::{File, FileMut};
+use inode::Ext2Inode;
+pub use inode::{FileType, Inode};
+use on_disk::OnDisk;
+pub use partition_device::PartitionDevice;
+
+on_disk_struct! {
+    #[allow(dead_code)]
+    struct Ext2SuperBlock {
+        s_inodes_count: u32,      /* Inodes count */
+        s_blocks_count: u32,      /* Blocks count */
+        s_r_blocks_count: u32,    /* Reserved blocks count */
+        s_free_blocks_count: u32, /* Free blocks count */
+        s_free_inodes_count: u32, /* Free inodes count */
+        s_first_data_block: u32,  /* First Data Block */
+        s_log_block_size: i32,    /* Block size */
+        s_log_frag_size: u32,     /* Fragment size */
+        s_blocks_per_group: u32,  /* # Blocks per group */
+        s_frags_per_group: u32,   /* # Fragments per group */
+        s_inodes_per_group: u32,  /* # Inodes per group */
+        s_mtime: u32,             /* Mount time */
+        s_wtime: u32,             /* Write time */
+        s_mnt_count: u16,         /* Mount count */
+        s_max_mnt_count: u16,     /* Maximal mount count */
+        s_magic: u16,             /* Magic signature */
+        s_state: u16,             /* File system state */
+        s_errors: u16,            /* Behaviour when detecting errors */
+        s_minor_rev_level: u16,   /* minor revision level */
+        s_lastcheck: u32,         /* time of last check */
+        s_checkinterval: u32,     /* max. time between checks */
+        s_creator_os: u32,        /* OS */
+        s_rev_level: u32,         /* Revision level */
+        s_def_resuid: u16,        /* Default uid for reserved blocks */
+        s_def_resgid: u16,        /* Default gid for reserved blocks */
+        /*
+         * These fields are for EXT2_DYNAMIC_REV superblocks only.
+         *
+         * Note: the difference between the compatible feature set and
+         * the incompatible feature set is that if there is a bit set
+         * in the incompatible feature set that the kernel doesn't
+         * know about, it should refuse to mount the filesystem.
+         *
+         * e2fsck's requirements are more strict; if it doesn't know
+         * about a feature in either the compatible or incompatible
+         * feature set, it must abort and not try to meddle with
+         * things it doesn't understand...
+         */
+        s_first_ino: u32,              /* First non-reserved inode */
+        s_inode_size: u16,             /* size of inode structure */
+        s_block_group_nr: u16,         /* block group # of this superblock */
+        s_feature_compat: u32,         /* compatible feature set */
+        s_feature_incompat: u32,       /* incompatible feature set */
+        s_feature_ro_compat: u32,      /* readonly-compatible feature set */
+        s_uuid: [u8; 16],              /* 128-bit uuid for volume */
+        s_volume_name: [u8; 16],       /* volume name */
+        s_last_mounted: [u8; 64],      /* directory where last mounted */
+        s_algorithm_usage_bitmap: u32, /* For compression */
+        /*
+         * Performance hints.  Directory preallocation should only
+         * happen if the EXT2_COMPAT_PREALLOC flag is on.
+         */
+        s_prealloc_blocks: u8,      /* Nr of blocks to try to preallocate*/
+        s_prealloc_dir_blocks: u8,  /* Nr to preallocate for dirs */
+        s_reserved_gdt_blocks: u16, /* Per group desc for online growth */
+        /*
+         * Journaling support valid if EXT3_FEATURE_COMPAT_HAS_JOURNAL set.
+         */
+        s_journal_uuid: [u8; 16], /* uuid of journal superblock */
+        s_journal_inum: u32,      /* inode number of journal file */
+        s_journal_dev: u32,       /* device number of journal file */
+        s_last_orphan: u32,       /* start of list of inodes to delete */
+        s_hash_seed: [u32; 4],    /* HTREE hash seed */
+        s_def_hash_version: u8,   /* Default hash version to use */
+        s_jnl_backup_type: u8,
+        s_desc_size: u16, /* size of group descriptor */
+        s_default_mount_opts: u32,
+        s_first_meta_bg: u32,    /* First metablock block group */
+        s_mkfs_time: u32,        /* When the filesystem was created */
+        s_jnl_blocks: [u32; 17], /* Backup of the journal inode */
+        /*
+         * The remaining fields are only used by ext4.
+         */
+        s_blocks_count_hi: u32,      /* Blocks count */
+        s_r_blocks_count_hi: u32,    /* Reserved blocks count */
+        s_free_blocks_count_hi: u32, /* Free blocks count */
+        s_min_extra_isize: u16,      /* All inodes have at least # bytes */
+        s_want_extra_isize: u16,     /* New inodes should reserve # bytes */
+        s_flags: u32,                /* Miscellaneous flags */
+        s_raid_stride: u16,          /* RAID stride */
+        s_mmp_update_interval: u16,  /* # seconds to wait in MMP checking */
+        s_mmp_block: u64,            /* Block for multi-mount protection */
+        s_raid_stripe_width: u32,    /* blocks on all data disks (N*stride)*/
+        s_log_groups_per_flex: u8,   /* FLEX_BG group size */
+        s_checksum_type: u8,         /* metadata checksum algorithm used */
+        s_encryption_level: u8,      /* versioning level for encryption */
+        s_reserved_pad: u8,
+        s_kbytes_written: u64,          /* nr of lifetime kilobytes written */
+        s_snapshot_inum: u32,           /* Inode number of active snapshot */
+        s_snapshot_id: u32,             /* sequential ID of active snapshot */
+        s_snapshot_r_blocks_count: u64, /* reserved blocks for active snapshot's future use */
+        s_snapshot_list: u32,           /* inode number of the head of the on-disk snapshot list */
+        s_error_count: u32,             /* number of fs errors */
+        s_first_error_time: u32,        /* first time an error happened */
+        s_first_error_ino: u32,         /* inode involved in first error */
+        s_first_error_block: u64,       /* block involved of first error */
+        s_first_error_func: [u8; 32],   /* function where the error happened */
+        s_first_error_line: u32,        /* line number where error happened */
+        s_last_error_time: u32,         /* most recent time of an error */
+        s_last_error_ino: u32,          /* inode involved in last error */
+        s_last_error_line: u32,         /* line number where error happened */
+        s_last_error_block: u64,        /* block involved of last error */
+        s_last_error_func: [u8; 32],    /* function where the error happened */
+        s_mount_opts: [u8; 64],
+        s_usr_quota_inum: u32,       /* inode for tracking user quota */
+        s_grp_quota_inum: u32,       /* inode for tracking group quota */
+        s_overhead_clusters: u32,    /* overhead blocks/clusters in fs */
+        s_backup_bgs: [u32; 2],      /* groups with sparse_super2 SBs */
+        s_encrypt_algos: [u8; 4],    /* Encryption algorithms in use  */
+        s_encrypt_pw_salt: [u8; 16], /* Salt used for string2key algorithm */
+        s_lpf_ino: u32,              /* Location of the lost+found inode */
+        s_prj_quota_inum: u32,       /* inode for tracking project quota */
+        s_checksum_seed: u32,        /* crc32c(uuid) if csum_seed set */
+        s_wtime_hi: u8,
+        s_mtime_hi: u8,
+        s_mkfs_time_hi: u8,
+        s_lastcheck_hi: u8,
+        s_first_error_time_hi: u8,
+        s_last_error_time_hi: u8,
+        s_first_error_errcode: u8,
+        s_last_error_errcode: u8,
+        s_encoding: u16,         /* Filename charset encoding */
+        s_encoding_flags: u16,   /* Filename charset encoding flags */
+        s_orphan_file_inum: u32, /* Inode for tracking orphan inodes */
+        s_reserved: [u32; 94],   /* Padding to the end of the block */
+        s_checksum: u32,         /* crc32c(superblock) */
+    }
+}
+
+/// Value of `s_state` bits: the filesystem was cleanly unmounted
+const EXT2_VALID_FS: u16 = 0x0001;
+
+/// Value of `s_state` bits: errors were detected
+const EXT2_ERROR_FS: u16 = 0x0002;
+
+/// Revision level with variable inode sizes and the extended superblock fields
+const EXT2_DYNAMIC_REV: u32 = 1;
+
+/// Incompatible features that were introduced by ext4: extents, 64bit, mmp, flex_bg, ea_inode,
+/// dirdata, csum_seed, largedir, inline_data and encrypt
+const EXT4_FEATURE_INCOMPAT_EXT4_MASK: IncompatFeatures = IncompatFeatures::from_bits(0x1F7C0);
+
+/// Read-only compatible features that were introduced by ext4: huge_file, gdt_csum, dir_nlink,
+/// extra_isize, quota, bigalloc and metadata_csum
+const EXT4_FEATURE_RO_COMPAT_EXT4_MASK: RoCompatFeatures = RoCompatFeatures::from_bits(0x0778);
+
+/// Incompatible features implemented by this crate. A journal that needs to be replayed is
+/// tolerated, but the filesystem is then only mounted for reading.
+const SUPPORTED_INCOMPAT_FEATURES: IncompatFeatures = IncompatFeatures::from_bits(
+    IncompatFeatures::FILETYPE.bits() | IncompatFeatures::RECOVER.bits(),
+);
+
+/// Read-only compatible features that this crate can preserve when writing to the filesystem
+const SUPPORTED_RO_COMPAT_FEATURES: RoCompatFeatures = RoCompatFeatures::from_bits(
+    RoCompatFeatures::SPARSE_SUPER.bits()
+        | RoCompatFeatures::LARGE_FILE.bits()
+        | RoCompatFeatures::BTREE_DIR.bits(),
+);
+
+/// Value of `s_checksum_type` for crc32c, the only algorithm defined for metadata_csum
+const EXT4_CRC32C_CHKSUM: u8 = 1;
+
+impl Ext2SuperBlock {
+    fn compat_features(&self) -> CompatFeatures {
+        CompatFeatures::from_bits(self.s_feature_compat)
+    }
+
+    fn incompat_features(&self) -> IncompatFeatures {
+        IncompatFeatures::from_bits(self.s_feature_incompat)
+    }
+
+    fn ro_compat_features(&self) -> RoCompatFeatures {
+        RoCompatFeatures::from_bits(self.s_feature_ro_compat)
+    }
+}
+
+/// Combines an on-disk timestamp, split into the low 32 bits and the high byte, into seconds
+/// since the Unix epoch. 0 means that the time was never set.
+fn epoch_seconds(time: u32, time_hi: u8) -> Option<u64> {
+    let time = time as u64 | ((time_hi as u64) << 32);
+    if time == 0 {
+        return None;
+    }
+    Some(time)
+}
+
+/// Converts seconds since the Unix epoch into a time
+#[cfg(feature = "std")]
+fn system_time(seconds: u64) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds)
+}
+
+on_disk_struct! {
+    /// Block group descriptor, as stored in the group descriptor table
+    #[allow(dead_code)]
+    pub struct Ext2GroupDescriptor {
+        bg_block_bitmap: u32,         /* Blocks bitmap block */
+        bg_inode_bitmap: u32,         /* Inodes bitmap block */
+        bg_inode_table: u32,          /* Inodes table block */
+        bg_free_blocks_count: u16,    /* Free blocks count */
+        bg_free_inodes_count: u16,    /* Free inodes count */
+        bg_used_dirs_count: u16,      /* Directories count */
+        bg_flags: u16,                /* EXT4_BG_flags (INODE_UNINIT, etc) */
+        bg_exclude_bitmap_lo: u32,    /* Exclude bitmap for snapshots */
+        bg_block_bitmap_csum_lo: u16, /* crc32c(s_uuid+grp_num+bbitmap) LE */
+        bg_inode_bitmap_csum_lo: u16, /* crc32c(s_uuid+grp_num+ibitmap) LE */
+        bg_itable_unused: u16,        /* Unused inodes count */
+        bg_checksum: u16,             /* crc16(sb_uuid+group+desc) */
+    }
+}
+
+impl Ext2GroupDescriptor {
+    /// Returns the block holding the block usage bitmap of the group
+    pub fn block_bitmap(&self) -> u32 {
+        self.bg_block_bitmap
+    }
+
+    /// Returns the block holding the inode usage bitmap of the group
+    pub fn inode_bitmap(&self) -> u32 {
+        self.bg_inode_bitmap
+    }
+
+    /// Returns the first block of the inode table of the group
+    pub fn inode_table(&self) -> u32 {
+        self.bg_inode_table
+    }
+
+    pub fn free_blocks_count(&self) -> u16 {
+        self.bg_free_blocks_count
+    }
+
+    pub fn free_inodes_count(&self) -> u16 {
+        self.bg_free_inodes_count
+    }
+
+    pub fn used_dirs_count(&self) -> u16 {
+        self.bg_used_dirs_count
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.bg_flags
+    }
+}
+
+/// Trait for a block device. It reads/writes in chunks given by the block size. The device block
+/// size doesn't need to match the block size of the filesystem stored in it.
 pub trait BlockDevice {
+    /// Error reported by the device when an I/O operation fails
+    type Error: core::fmt::Debug + Send + Sync + 'static;
+
     /// Reads multiple blocks from the device. The size of the returned block can be obtained with
     /// `get_block_size`
-    fn read_blocks(&self, index: usize, num_blocks: usize) -> Vec<u8>;
+    fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, Self::Error>;
 
     /// Writes to the device. The size of the block can be obtained with
     /// `get_block_size`
-    fn write_blocks(&mut self, index: usize, data: &[u8]);
+    fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), Self::Error>;
 
     /// Returns the block size of the device
     fn get_block_size(&self) -> usize;
+
+    /// Pushes any write the device may be holding back to the storage. Devices that write
+    /// synchronously don't need to implement it.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
+    /// The block device failed to complete an operation
+    DeviceError(Box<dyn core::fmt::Debug + Send + Sync>),
+    /// The block device returned less data than requested
+    ShortRead,
     NoFilesystemFound,
+    /// The superblock holds values that can't describe a valid filesystem
+    InvalidSuperblock,
+    /// The block size of the device, given in bytes, isn't a power of two
+    UnsupportedBlockSize(usize),
+    UnsupportedFeature,
+    /// The filesystem uses incompatible features that aren't implemented, given as the raw
+    /// `s_feature_incompat` bits
+    UnsupportedFeatures(u32),
+    /// The filesystem can only be read, because it uses read-only compatible features that
+    /// aren't implemented
+    ReadOnly,
+    InvalidArgument,
+    InvalidBlockGroup,
+    CorruptedGroupDescriptors,
+    /// The inode number is out of range, or the inode claims to be larger than any file on the
+    /// filesystem can be
+    InvalidInode,
+    CorruptedInode,
+    NotADirectory,
+    NotASymlink,
+    /// Resolving a path went through too many symlinks, most likely a loop
+    TooManySymlinks,
+    CorruptedDirectory,
+    /// A directory entry records a file type that isn't known, reported by strict directory
+    /// iterators
+    UnknownFileType(u8),
+    /// A path that should be absolute doesn't start with `/`
+    InvalidPath,
+    /// A component of a path doesn't exist
+    NotFound(String),
+    /// A directory already has an entry with the given name
+    AlreadyExists(String),
+    IsADirectory,
+    /// There are no free blocks or inodes left
+    NoSpaceLeft,
+}
+
+impl Error {
+    fn device<E: core::fmt::Debug + Send + Sync + 'static>(error: E) -> Self {
+        Error::DeviceError(Box::new(error))
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::DeviceError(error) => write!(f, "device error: {:?}", error),
+            Error::ShortRead => write!(f, "the device returned less data than requested"),
+            Error::NoFilesystemFound => write!(f, "no ext2 filesystem found"),
+            Error::InvalidSuperblock => write!(f, "invalid superblock"),
+            Error::UnsupportedBlockSize(size) => {
+                write!(f, "unsupported device block size of {} bytes", size)
+            }
+            Error::UnsupportedFeature => write!(f, "unsupported filesystem feature"),
+            Error::UnsupportedFeatures(bits) => {
+                write!(
+                    f,
+                    "unsupported incompatible features: {:?}",
+                    IncompatFeatures::from_bits(*bits)
+                )
+            }
+            Error::ReadOnly => write!(f, "the filesystem is read-only"),
+            Error::InvalidArgument => write!(f, "invalid argument"),
+            Error::InvalidBlockGroup => write!(f, "invalid block group"),
+            Error::CorruptedGroupDescriptors => write!(f, "corrupted group descriptors"),
+            Error::InvalidInode => write!(f, "invalid inode"),
+            Error::CorruptedInode => write!(f, "corrupted inode"),
+            Error::NotADirectory => write!(f, "not a directory"),
+            Error::NotASymlink => write!(f, "not a symlink"),
+            Error::TooManySymlinks => write!(f, "too many levels of symlinks"),
+            Error::CorruptedDirectory => write!(f, "corrupted directory"),
+            Error::UnknownFileType(file_type) => write!(f, "unknown file type {}", file_type),
+            Error::InvalidPath => write!(f, "path is not absolute"),
+            Error::NotFound(name) => write!(f, "{} not found", name),
+            Error::AlreadyExists(name) => write!(f, "{} already exists", name),
+            Error::IsADirectory => write!(f, "is a directory"),
+            Error::NoSpaceLeft => write!(f, "no space left on the filesystem"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Returns the bytes of a fixed size on-disk string up to its NUL terminator, if it has one
+fn nul_terminated(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Decodes a fixed size on-disk string. Invalid UTF-8 ends the string.
+fn nul_terminated_str(bytes: &[u8]) -> &str {
+    let bytes = nul_terminated(bytes);
+    match core::str::from_utf8(bytes) {
+        Ok(string) => string,
+        Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap(),
+    }
+}
+
+/// Flavour of the extended filesystem, as `blkid` would report it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilesystemKind {
+    Ext2,
+    Ext3,
+    Ext4,
+}
+
+/// Identification of a filesystem obtained with `probe`
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub uuid: [u8; 16],
+    pub label: String,
+    pub block_size: usize,
+    pub feature_compat: CompatFeatures,
+    pub feature_incompat: IncompatFeatures,
+    pub feature_ro_compat: RoCompatFeatures,
+    pub kind: FilesystemKind,
+}
+
+/// Identifies the filesystem on `device` by reading only its superblock. This is much cheaper
+/// than mounting it with `Ext2Fs::new`.
+pub fn probe<D: BlockDevice>(device: &D) -> Result<Probe, Error> {
+    let superblock = Ext2Fs::<D>::read_superblock(device)?;
+
+    let kind = if superblock
+        .incompat_features()
+        .intersects(EXT4_FEATURE_INCOMPAT_EXT4_MASK)
+        || superblock
+            .ro_compat_features()
+            .intersects(EXT4_FEATURE_RO_COMPAT_EXT4_MASK)
+    {
+        FilesystemKind::Ext4
+    } else if superblock
+        .compat_features()
+        .contains(CompatFeatures::HAS_JOURNAL)
+    {
+        FilesystemKind::Ext3
+    } else {
+        FilesystemKind::Ext2
+    };
+
+    Ok(Probe {
+        uuid: superblock.s_uuid,
+        label: nul_terminated_str(&superblock.s_volume_name).to_string(),
+        block_size: Ext2Fs::<D>::superblock_block_size(&superblock),
+        feature_compat: superblock.compat_features(),
+        feature_incompat: superblock.incompat_features(),
+        feature_ro_compat: superblock.ro_compat_features(),
+        kind,
+    })
+}
+
+/// Active snapshot recorded in the superblock by next3 and snapshot-capable ext4 variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    /// Inode of the active snapshot
+    pub inode: u32,
+    /// Sequential id of the active snapshot
+    pub id: u32,
+    /// Blocks reserved for the active snapshot's future use
+    pub reserved_blocks: u64,
+    /// Inode at the head of the on-disk snapshot list
+    pub list_head: u32,
+}
+
+/// Overall state of the filesystem as recorded on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountState {
+    /// Cleanly unmounted, without recorded errors
+    Clean,
+    /// Not cleanly unmounted, or currently mounted
+    Dirty,
+    /// Errors were detected, a filesystem check is recommended
+    HasErrors,
+    /// The journal has to be replayed before the filesystem is consistent
+    NeedsRecovery,
+}
+
+/// Details about an error recorded in the superblock by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRecord {
+    /// Time of the error, in seconds since the Unix epoch
+    pub time: u64,
+    pub inode: u32,
+    pub block: u64,
+    /// Name of the kernel function that reported the error
+    pub function: Vec<u8>,
+    pub line: u32,
+}
+
+impl ErrorRecord {
+    /// Returns the time of the error
+    #[cfg(feature = "std")]
+    pub fn system_time(&self) -> std::time::SystemTime {
+        system_time(self.time)
+    }
+}
+
+/// Error diagnostics kept in the superblock, as printed by `dumpe2fs`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLog {
+    pub count: u32,
+    pub first: Option<ErrorRecord>,
+    pub last: Option<ErrorRecord>,
+}
+
+/// Kind of quota tracked by the filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaKind {
+    User,
+    Group,
+    Project,
+}
+
+/// Space usage of the filesystem, as reported by `statfs(2)` and `df`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    pub block_size: usize,
+    /// Blocks available for data, which excludes the metadata overhead
+    pub blocks: u64,
+    pub free_blocks: u64,
+    /// Free blocks that aren't reserved for the super user
+    pub available_blocks: u64,
+    pub inodes: u64,
+    pub free_inodes: u64,
+}
+
+/// Range of logical file blocks stored in consecutive physical blocks
+#[derive(Debug, Clone, Copy)]
+struct BlockRun {
+    logical: usize,
+    physical: usize,
+    len: usize,
+}
+
+/// Block map of a file as `Ext2Fs::block_map` builds it, one pointer at a time
+struct BlockMap {
+    runs: Vec<BlockRun>,
+    /// Logical block mapped by the next pointer
+    next: usize,
+    /// Number of logical blocks of the file
+    len: usize,
+    /// Number of data blocks mapped so far
+    mapped: usize,
+    /// Number of blocks of the filesystem
+    blocks_count: usize,
+}
+
+impl BlockMap {
+    fn is_complete(&self) -> bool {
+        self.next >= self.len
+    }
+
+    /// Skips `count` logical blocks, which are a hole
+    fn skip(&mut self, count: usize) {
+        self.next = self.len.min(self.next.saturating_add(count));
+    }
+
+    /// Maps the next logical block to `physical`, 0 meaning a hole
+    fn push(&mut self, physical: u32) -> Result<(), Error> {
+        let logical = self.next;
+        self.next += 1;
+        if physical == 0 {
+            return Ok(());
+        }
+
+        // Unless its pointers are corrupted, a file doesn't map any block twice, so it can't map
+        // more blocks than the filesystem has
+        let physical = physical as usize;
+        self.mapped += 1;
+        if physical >= self.blocks_count || self.mapped > self.blocks_count {
+            return Err(Error::CorruptedInode);
+        }
+
+        match self.runs.last_mut() {
+            Some(run) if run.logical + run.len == logical && run.physical + run.len == physical => {
+                run.len += 1
+            }
+            _ => self.runs.push(BlockRun {
+                logical,
+                physical,
+                len: 1,
+            }),
+        }
+        Ok(())
+    }
 }
 
 /// Representation of an ext2 filesystem
 pub struct Ext2Fs<T: BlockDevice> {
     device: T,
-    superblock: Option<Ext2SuperBlock>,
-    cached_group_descriptor: Ext2GroupDescriptor,
+    superblock: Ext2SuperBlock,
+    group_descriptors: Vec<Ext2GroupDescriptor>,
     block_size: usize,
     num_block_groups: usize,
+    read_only: bool,
+    /// Block groups whose free counts changed in memory, see `write_counters`
+    dirty_groups: BTreeSet<usize>,
 }
 
 impl<T: BlockDevice> Ext2Fs<T> {
     const DEFAULT_BLOCK_SIZE: usize = 1024;
     const SUPERBLOCK_OFFSET: usize = 1024;
+    const GOOD_OLD_INODE_SIZE: usize = 128;
 
-    /// Constructor for an ext2 filesystem. It takes ownership of the underlying block device.
-    pub fn new(device: T) -> Self {
-        Ext2Fs {
-            device,
-            superblock: None,
-            cached_group_descriptor: Default::default(),
-            block_size: 1024,
-            num_block_groups: 0,
+    /// Largest `s_log_block_size`, for 64KiB blocks
+    const MAX_LOG_BLOCK_SIZE: i32 = 6;
+
+    /// Maximum number of symlinks followed while resolving a path, the same limit as Linux
+    const MAX_SYMLINKS: usize = 40;
+
+    /// Inode number of the root directory
+    pub const ROOT_INODE: u32 = 2;
+
+    /// Mounts the ext2 filesystem stored in the device. It takes ownership of the underlying
+    /// block device.
+    pub fn new(device: T) -> Result<Self, Error> {
+        let superblock = Self::read_superblock(&device)?;
+
+        // Mounting with an incompatible feature we don't implement would misinterpret the data
+        let unsupported =
+            superblock.incompat_features().bits() & !SUPPORTED_INCOMPAT_FEATURES.bits();
+        if unsupported != 0 {
+            return Err(Error::UnsupportedFeatures(unsupported));
+        }
+
+        // Checksums computed with an algorithm we don't know about cannot be verified
+        if superblock
+            .ro_compat_features()
+            .contains(RoCompatFeatures::METADATA_CSUM)
+            && superblock.s_checksum_type != EXT4_CRC32C_CHKSUM
+        {
+            return Err(Error::UnsupportedFeature);
         }
+
+        let read_only = superblock
+            .incompat_features()
+            .contains(IncompatFeatures::RECOVER)
+            || !SUPPORTED_RO_COMPAT_FEATURES.contains(superblock.ro_compat_features());
+        let block_size = Self::superblock_block_size(&superblock);
+        // Blocks before `s_first_data_block` don't belong to any group
+        let num_block_groups = (superblock.s_blocks_count - superblock.s_first_data_block)
+            .div_ceil(superblock.s_blocks_per_group) as usize;
+
+        let mut ext2fs = Ext2Fs {
+            device,
+            superblock,
+            group_descriptors: vec![],
+            block_size,
+            num_block_groups,
+            read_only,
+            dirty_groups: BTreeSet::new(),
+        };
+        ext2fs.group_descriptors = ext2fs.read_group_descriptors()?;
+        Ok(ext2fs)
     }
 
-    fn read_superblock(&mut self) -> Result<Ext2SuperBlock, Error> {
-        let block_size = self.device.get_block_size();
+    /// Mounts an ext2 filesystem whose device reads are served through an LRU cache of
+    /// `capacity` blocks. Writes go straight through to the device.
+    pub fn with_cache(device: T, capacity: usize) -> Result<Ext2Fs<CachingDevice<T>>, Error> {
+        Ext2Fs::new(CachingDevice::new(device, capacity))
+    }
+
+    /// Mounts an ext2 filesystem keeping up to `num_blocks` device blocks in a write-back LRU
+    /// cache. Writes only reach the device when `flush` is called, when cached blocks are
+    /// evicted, or when the filesystem is dropped.
+    pub fn with_write_back_cache(
+        device: T,
+        num_blocks: usize,
+    ) -> Result<Ext2Fs<CachingDevice<T>>, Error> {
+        Ext2Fs::new(CachingDevice::write_back(device, num_blocks))
+    }
+
+    /// Mounts an ext2 filesystem that starts `byte_offset` bytes into the device, e.g. a
+    /// partition of a whole-disk image. The offset must be a multiple of the device block size.
+    pub fn new_at_offset(
+        device: T,
+        byte_offset: usize,
+    ) -> Result<Ext2Fs<PartitionDevice<T>>, Error> {
+        Ext2Fs::new(PartitionDevice::new(device, byte_offset)?)
+    }
+
+    fn read_superblock(device: &T) -> Result<Ext2SuperBlock, Error> {
+        // Filesystem block sizes are powers of two, so with a device block size that is one too,
+        // blocks of either size always divide the blocks of the other evenly
+        let block_size = device.get_block_size();
+        if !block_size.is_power_of_two() {
+            return Err(Error::UnsupportedBlockSize(block_size));
+        }
 
         // The superblock is located at a fixed 1024 byte offset in the disk
         let index = Self::SUPERBLOCK_OFFSET / block_size;
         let offset = Self::SUPERBLOCK_OFFSET % block_size;
-        let block_count = if std::mem::size_of::<Ext2SuperBlock>() > (block_size - offset) {
-            let remaining_bytes = std::mem::size_of::<Ext2SuperBlock>() - (block_size - offset);
-            1 + remaining_bytes.div_ceil(&block_size)
+        let block_count = if Ext2SuperBlock::SIZE > (block_size - offset) {
+            let remaining_bytes = Ext2SuperBlock::SIZE - (block_size - offset);
+            1 + remaining_bytes.div_ceil(block_size)
         } else {
             1
         };
 
-        let superblock_data = self.device.read_blocks(index, block_count);
-        let superblock_ptr = superblock_data[offset..].as_ptr() as *const Ext2SuperBlock;
-        // SAFETY: 1. It is guaranteed that superblock_ptr will contain enough data for the
-        //            superblock, since we read enough data.
-        //         2. since the superblock is made of primitive types, its state cannot be invalid.
-        //         3. Layout is guaranteed, the Ext2SuperBlock struct is declared with repr(C)
-        let superblock: Ext2SuperBlock = unsafe { std::mem::transmute_copy(&*superblock_ptr) };
+        let superblock_data = device
+            .read_blocks(index, block_count)
+            .map_err(Error::device)?;
+        if superblock_data.len() < offset + Ext2SuperBlock::SIZE {
+            return Err(Error::ShortRead);
+        }
+        let superblock = Ext2SuperBlock::parse(&superblock_data[offset..]);
 
         if superblock.s_magic != 0xEF53 {
             return Err(Error::NoFilesystemFound);
         }
 
+        // Everything else is computed from these, so reject values that would make no sense
+        // instead of dividing by zero or overflowing later on
+        if !(0..=Self::MAX_LOG_BLOCK_SIZE).contains(&superblock.s_log_block_size)
+            || superblock.s_first_data_block >= superblock.s_blocks_count
+        {
+            return Err(Error::InvalidSuperblock);
+        }
+
+        // Each group tracks its blocks and inodes in a single bitmap block
+        let bitmap_capacity = 8 * Self::superblock_block_size(&superblock) as u32;
+        if !(1..=bitmap_capacity).contains(&superblock.s_blocks_per_group)
+            || !(1..=bitmap_capacity).contains(&superblock.s_inodes_per_group)
+        {
+            return Err(Error::InvalidSuperblock);
+        }
+        // Every inode has to belong to a group
+        let num_block_groups = (superblock.s_blocks_count - superblock.s_first_data_block)
+            .div_ceil(superblock.s_blocks_per_group) as u64;
+        if superblock.s_inodes_count as u64
+            > num_block_groups * superblock.s_inodes_per_group as u64
+        {
+            return Err(Error::InvalidSuperblock);
+        }
+        if superblock.s_rev_level >= EXT2_DYNAMIC_REV {
+            let inode_size = superblock.s_inode_size as usize;
+            if !inode_size.is_power_of_two()
+                || inode_size < Self::GOOD_OLD_INODE_SIZE
+                || inode_size > Self::superblock_block_size(&superblock)
+            {
+                return Err(Error::InvalidSuperblock);
+            }
+            // Inodes are allocated from the first non-reserved one
+            if !(1..=superblock.s_inodes_count).contains(&superblock.s_first_ino) {
+                return Err(Error::InvalidSuperblock);
+            }
+        }
+
         Ok(superblock)
     }
 
-    pub fn initialize(&mut self) -> Result<(), Error> {
-        self.superblock = Some(self.read_superblock()?);
-        let superblock = self.superblock.as_ref().unwrap();
+    /// Reads `count` filesystem blocks starting at filesystem block `block`. The device block
+    /// size may be smaller than the filesystem block size, in which case several device blocks
+    /// are read for each filesystem block, or larger, in which case only part of the device
+    /// blocks is returned.
+    fn read_fs_blocks(&self, block: usize, count: usize) -> Result<Vec<u8>, Error> {
+        let device_block_size = self.device.get_block_size();
+        let offset = block * self.block_size;
+        let len = count * self.block_size;
 
-        // Get block size
-        let log_block_size = superblock.s_log_block_size;
-        self.block_size = if log_block_size < 0 {
-            Self::DEFAULT_BLOCK_SIZE >> -log_block_size
-        } else {
-            Self::DEFAULT_BLOCK_SIZE << log_block_size
-        };
+        let index = offset / device_block_size;
+        let start = offset % device_block_size;
+        let device_blocks = (start + len).div_ceil(device_block_size);
+
+        let data = self
+            .device
+            .read_blocks(index, device_blocks)
+            .map_err(Error::device)?;
+        if data.len() < start + len {
+            return Err(Error::ShortRead);
+        }
+        Ok(data[start..start + len].to_vec())
+    }
+
+    /// Writes `data`, a whole number of filesystem blocks, starting at filesystem block `block`.
+    /// Filesystem blocks that only cover part of a device block are merged with its current
+    /// contents.
+    fn write_fs_blocks(&mut self, block: usize, data: &[u8]) -> Result<(), Error> {
+        let device_block_size = self.device.get_block_size();
+        let offset = block * self.block_size;
+
+        let index = offset / device_block_size;
+        let start = offset % device_block_size;
+        if start == 0 && data.len() % device_block_size == 0 {
+            return self.device.write_blocks(index, data).map_err(Error::device);
+        }
+
+        // The blocks share device blocks with other data, so read-modify-write them
+        let device_blocks = (start + data.len()).div_ceil(device_block_size);
+        let mut buffer = self
+            .device
+            .read_blocks(index, device_blocks)
+            .map_err(Error::device)?;
+        if buffer.len() < start + data.len() {
+            return Err(Error::ShortRead);
+        }
+        buffer[start..start + data.len()].copy_from_slice(data);
+        self.device
+            .write_blocks(index, &buffer)
+            .map_err(Error::device)
+    }
+
+    fn read_group_descriptors(&self) -> Result<Vec<Ext2GroupDescriptor>, Error> {
+        let superblock = &self.superblock;
+
+        // The descriptor table starts in the block following the superblock
+        let descriptor_size = Ext2GroupDescriptor::SIZE;
+        let table_size = self.num_block_groups * descriptor_size;
+        let table_blocks = table_size.div_ceil(self.block_size);
+        let table =
+            self.read_fs_blocks(superblock.s_first_data_block as usize + 1, table_blocks)?;
+
+        let inode_table_blocks =
+            (superblock.s_inodes_per_group as usize * self.inode_size()).div_ceil(self.block_size);
+        let blocks_count = superblock.s_blocks_count as usize;
+        // Each group has a single block for each of its bitmaps
+        let bitmap_capacity = 8 * self.block_size;
+
+        let mut free_blocks: u64 = 0;
+        let mut free_inodes: u64 = 0;
+        let mut descriptors = Vec::with_capacity(self.num_block_groups);
+        for chunk in table
+            .chunks_exact(descriptor_size)
+            .take(self.num_block_groups)
+        {
+            let descriptor = Ext2GroupDescriptor::parse(chunk);
+
+            if descriptor.bg_block_bitmap as usize >= blocks_count
+                || descriptor.bg_inode_bitmap as usize >= blocks_count
+                || descriptor.bg_inode_table as usize + inode_table_blocks > blocks_count
+                || descriptor.bg_free_blocks_count as usize > bitmap_capacity
+                || descriptor.bg_free_inodes_count as usize > bitmap_capacity
+            {
+                return Err(Error::CorruptedGroupDescriptors);
+            }
+
+            free_blocks = free_blocks
+                .checked_add(descriptor.bg_free_blocks_count as u64)
+                .ok_or(Error::CorruptedGroupDescriptors)?;
+            free_inodes = free_inodes
+                .checked_add(descriptor.bg_free_inodes_count as u64)
+                .ok_or(Error::CorruptedGroupDescriptors)?;
+            descriptors.push(descriptor);
+        }
+
+        if free_blocks != superblock.s_free_blocks_count as u64
+            || free_inodes != superblock.s_free_inodes_count as u64
+        {
+            return Err(Error::CorruptedGroupDescriptors);
+        }
+
+        Ok(descriptors)
+    }
+
+    fn write_superblock(&mut self) -> Result<(), Error> {
+        let block_size = self.device.get_block_size();
+
+        let size = Ext2SuperBlock::SIZE;
+        let index = Self::SUPERBLOCK_OFFSET / block_size;
+        let offset = Self::SUPERBLOCK_OFFSET % block_size;
+        let block_count = (offset + size).div_ceil(block_size);
+
+        // The superblock may share device blocks with other data, so read-modify-write them
+        let mut data = self
+            .device
+            .read_blocks(index, block_count)
+            .map_err(Error::device)?;
+        if data.len() < offset + size {
+            return Err(Error::ShortRead);
+        }
+        self.superblock.serialize(&mut data[offset..offset + size]);
+        self.device
+            .write_blocks(index, &data)
+            .map_err(Error::device)
+    }
 
-        // Extract number of block groups
-        self.num_block_groups = superblock
-            .s_blocks_count
-            .div_ceil(&superblock.s_blocks_per_group) as usize;
+    /// Writes a copy of the superblock at the start of every block group that holds a backup,
+    /// each recording the number of its group
+    fn write_backup_superblocks(&mut self) -> Result<(), Error> {
+        let first_data_block = self.superblock.s_first_data_block as usize;
+        let blocks_per_group = self.superblock.s_blocks_per_group as usize;
+        let blocks_count = self.superblock.s_blocks_count as usize;
 
+        for group in 1..self.num_block_groups {
+            let block = first_data_block + group * blocks_per_group;
+            if !self.group_has_superblock(group) || block >= blocks_count {
+                continue;
+            }
+
+            let mut data = self.read_fs_blocks(block, 1)?;
+            self.superblock.s_block_group_nr = group as u16;
+            self.superblock.serialize(&mut data);
+            self.superblock.s_block_group_nr = 0;
+            self.write_fs_blocks(block, &data)?;
+        }
         Ok(())
     }
 
-    pub fn block_size(&self) -> usize {
-        self.block_size
+    /// Returns the block size of the filesystem. `read_superblock` makes sure that it is valid.
+    fn superblock_block_size(superblock: &Ext2SuperBlock) -> usize {
+        Self::DEFAULT_BLOCK_SIZE << superblock.s_log_block_size
     }
 
-    pub fn num_block_groups(&self) -> usize {
-        self.num_block_groups
+    /// Writes back everything the device holds in memory, such as the dirty blocks of the cache
+    /// used by `with_write_back_cache`
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.device.flush().map_err(Error::device)
     }
 
-    pub fn num_blocks(&self) -> usize {
-        if let Some(superblock) = self.superblock.as_ref() {
-            return superblock.s_blocks_count as usize;
+    /// Returns the descriptor of the given block group
+    pub fn group_descriptor(&self, group: usize) -> Result<&Ext2GroupDescriptor, Error> {
+        self.group_descriptors
+            .get(group)
+            .ok_or(Error::InvalidBlockGroup)
+    }
+
+    /// Reads the inode with the given number. Inode numbers start at 1.
+    pub fn read_inode(&self, ino: u32) -> Result<Inode, Error> {
+        let (block, offset) = self.inode_location(ino)?;
+        let data = self.read_fs_blocks(block, 1)?;
+        let raw = Ext2Inode::parse(&data[offset..]);
+
+        Ok(Inode::new(ino, raw))
+    }
+
+    /// Returns the block of the inode table holding the given inode, and the offset of the inode
+    /// within that block
+    fn inode_location(&self, ino: u32) -> Result<(usize, usize), Error> {
+        let superblock = &self.superblock;
+        if ino == 0 || ino > superblock.s_inodes_count {
+            return Err(Error::InvalidInode);
         }
-        0
+
+        let group = ((ino - 1) / superblock.s_inodes_per_group) as usize;
+        let index = ((ino - 1) % superblock.s_inodes_per_group) as usize;
+        let descriptor = self.group_descriptor(group)?;
+
+        // Inodes may be larger than the structure we know about, so use the inode size as stride
+        let offset = index * self.inode_size();
+        let block = descriptor.bg_inode_table as usize + offset / self.block_size;
+        // An inode never crosses a block boundary
+        Ok((block, offset % self.block_size))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::prelude::*;
+    /// Reads the whole contents of the file described by `inode`. Holes read as zeros.
+    pub fn read_file(&self, inode: &Inode) -> Result<Vec<u8>, Error> {
+        // Holes are filled in as the runs around them are read, and the one at the end last
+        let mut data = vec![];
+        for run in self.block_map(inode)? {
+            data.resize(run.logical * self.block_size, 0);
+            data.extend_from_slice(&self.read_fs_blocks(run.physical, run.len)?);
+        }
 
-    struct FileDevice {
-        data: Vec<u8>,
+        data.resize(inode.size() as usize, 0);
+        Ok(data)
     }
 
-    impl FileDevice {
-        const BLOCK_SIZE: usize = 1024;
+    /// Reads the whole contents of the file described by `inode` like `read_file`, issuing the
+    /// device reads concurrently. This only pays off on devices that serve independent reads in
+    /// parallel, such as NVMe drives or object storage.
+    #[cfg(feature = "rayon")]
+    pub fn read_file_parallel(&self, inode: &Inode) -> Result<Vec<u8>, Error>
+    where
+        T: Sync,
+    {
+        use rayon::prelude::*;
 
-        fn new(path: &std::path::Path) -> Self {
-            let mut file = std::fs::File::open(path).unwrap();
-            let mut dev = FileDevice { data: vec![] };
-            file.read_to_end(&mut dev.data).unwrap();
-            dev
+        const MAX_BLOCKS_PER_READ: usize = 32;
+
+        let runs = self.block_map(inode)?;
+        let mut data = vec![0; self.file_blocks(inode)? * self.block_size];
+
+        // Split the buffer in one slice per read, so that they can be filled independently
+        let mut reads = vec![];
+        let mut rest = data.as_mut_slice();
+        let mut position = 0;
+        let runs = runs.into_iter().flat_map(|run| {
+            (0..run.len)
+                .step_by(MAX_BLOCKS_PER_READ)
+                .map(move |start| BlockRun {
+                    logical: run.logical + start,
+                    physical: run.physical + start,
+                    len: MAX_BLOCKS_PER_READ.min(run.len - start),
+                })
+        });
+        for run in runs {
+            let (_, tail) =
+                core::mem::take(&mut rest).split_at_mut((run.logical - position) * self.block_size);
+            let (slice, tail) = tail.split_at_mut(run.len * self.block_size);
+            reads.push((run, slice));
+            rest = tail;
+            position = run.logical + run.len;
+        }
+
+        reads.into_par_iter().try_for_each(|(run, slice)| {
+            slice.copy_from_slice(&self.read_fs_blocks(run.physical, run.len)?);
+            Ok(())
+        })?;
+
+        data.truncate(inode.size() as usize);
+        Ok(data)
+    }
+
+    /// Returns an iterator over the entries of the directory described by `inode`. Deleted
+    /// entries are skipped.
+    pub fn read_dir(&self, inode: &Inode) -> Result<DirIterator<'_, T>, Error> {
+        if inode.file_type() != FileType::Directory {
+            return Err(Error::NotADirectory);
         }
+
+        let has_file_type = self
+            .superblock
+            .incompat_features()
+            .contains(IncompatFeatures::FILETYPE);
+        Ok(DirIterator::new(
+            self,
+            self.dir_blocks(inode)?,
+            has_file_type,
+        ))
     }
 
-    impl BlockDevice for FileDevice {
-        fn read_blocks(&self, index: usize, num_blocks: usize) -> Vec<u8> {
-            self.data
-                .iter()
-                .cloned()
-                .skip(index * FileDevice::BLOCK_SIZE)
-                .take(FileDevice::BLOCK_SIZE * num_blocks)
-                .collect()
+    /// Returns the blocks of a directory in logical order. Directories can't have holes, so the
+    /// list stops at the first one with a 0, for the caller to report when it gets there.
+    fn dir_blocks(&self, inode: &Inode) -> Result<Vec<u32>, Error> {
+        let mut blocks = vec![];
+        for run in self.block_map(inode)? {
+            if run.logical != blocks.len() {
+                break;
+            }
+            blocks.extend((run.physical..run.physical + run.len).map(|block| block as u32));
+        }
+
+        if blocks.len() < self.file_blocks(inode)? {
+            blocks.push(0);
         }
+        Ok(blocks)
+    }
 
-        fn write_blocks(&mut self, _index: usize, _data: &[u8]) {}
+    /// Resolves an absolute path into the inode it refers to. Repeated and trailing slashes are
+    /// ignored, and `.` and `..` components are resolved through the directory entries. Symlinks
+    /// are not followed, see `resolve` for that. There is no current directory to resolve
+    /// relative paths from, so they are rejected with `Error::InvalidPath`.
+    pub fn lookup(&self, path: &str) -> Result<Inode, Error> {
+        self.walk_path(path, false)
+    }
 
-        fn get_block_size(&self) -> usize {
-            FileDevice::BLOCK_SIZE
+    /// Resolves an absolute path like `lookup` does, but following symlinks in any component,
+    /// including the last one
+    pub fn resolve(&self, path: &str) -> Result<Inode, Error> {
+        self.walk_path(path, true)
+    }
+
+    /// Opens the file at the given absolute path, following symlinks
+    pub fn open(&self, path: &str) -> Result<File<'_, T>, Error> {
+        Ok(File::new(self, self.resolve(path)?))
+    }
+
+    /// Opens the file at the given absolute path for reading and writing, following symlinks
+    pub fn open_mut(&mut self, path: &str) -> Result<FileMut<'_, T>, Error> {
+        self.check_writable()?;
+        let inode = self.resolve(path)?;
+        Ok(FileMut::new(self, inode))
+    }
+
+    /// Returns the target of the symlink described by `inode`. Short targets are stored in the
+    /// inode itself, longer ones in a data block.
+    pub fn read_link(&self, inode: &Inode) -> Result<String, Error> {
+        if inode.file_type() != FileType::Symlink {
+            return Err(Error::NotASymlink);
         }
+
+        let size = inode.size() as usize;
+        let target = if inode.is_fast_symlink(self.block_size) {
+            let data = inode.inline_data();
+            if size > data.len() {
+                return Err(Error::CorruptedInode);
+            }
+            data[..size].to_vec()
+        } else {
+            let mut data = self.read_file(inode)?;
+            data.truncate(size);
+            data
+        };
+        String::from_utf8(target).map_err(|_| Error::CorruptedInode)
     }
 
-    #[test]
-    fn read_superblock() {
-        let path = std::path::PathBuf::from("ext2fs.bin");
-        let dev = FileDevice::new(&path);
-        let mut ext2fs = Ext2Fs::new(dev);
+    fn walk_path(&self, path: &str, follow_symlinks: bool) -> Result<Inode, Error> {
+        if !path.starts_with('/') {
+            return Err(Error::InvalidPath);
+        }
 
-        ext2fs.initialize().unwrap();
-        let superblock = ext2fs.superblock.as_ref().unwrap();
-        assert_eq!(superblock.s_magic, 0xEF53);
-        assert_eq!(superblock.s_creator_os, 0);
-        assert_eq!(superblock.s_state, 1);
-        assert_eq!(superblock.s_blocks_per_group, 32768); // 32768 blocks per group
-        assert_eq!(superblock.s_log_block_size, 2); // 4096 bytes/ block
-        assert_eq!(superblock.s_block_group_nr, 0);
+        let mut inode = self.read_inode(Self::ROOT_INODE)?;
+        let mut symlinks = 0;
 
-        assert_eq!(ext2fs.block_size(), 4096);
-        assert_eq!(ext2fs.num_block_groups(), 1);
-        assert_eq!(ext2fs.num_blocks(), 256);
+        // Components still to resolve, in reverse order
+        let mut components: Vec<String> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .rev()
+            .map(|component| component.to_string())
+            .collect();
+        while let Some(component) = components.pop() {
+            if component == "." {
+                continue;
+            }
+
+            let entry = self.find_entry(&inode, &component)?;
+            let next = self.read_inode(entry.inode)?;
+            if follow_symlinks && next.file_type() == FileType::Symlink {
+                symlinks += 1;
+                if symlinks > Self::MAX_SYMLINKS {
+                    return Err(Error::TooManySymlinks);
+                }
+
+                // Relative targets start from the directory holding the symlink
+                let target = self.read_link(&next)?;
+                if target.starts_with('/') {
+                    inode = self.read_inode(Self::ROOT_INODE)?;
+                }
+                components.extend(
+                    target
+                        .split('/')
+                        .filter(|component| !component.is_empty())
+                        .rev()
+                        .map(|component| component.to_string()),
+                );
+                continue;
+            }
+            inode = next;
+        }
+        Ok(inode)
+    }
+
+    /// Finds the entry with the given name in the directory described by `dir`
+    fn find_entry(&self, dir: &Inode, name: &str) -> Result<DirEntry, Error> {
+        for entry in self.read_dir(dir)? {
+            let entry = entry?;
+            if entry.name == name {
+                return Ok(entry);
+            }
+        }
+        Err(Error::NotFound(name.to_string()))
+    }
+
+    /// Returns the number of blocks spanned by the size of the file
+    fn file_blocks(&self, inode: &Inode) -> Result<usize, Error> {
+        // The size comes straight from the disk, so don't trust it to size anything. A file can't
+        // be larger than what its block pointers address. Sparse files may well be larger than
+        // the filesystem itself, so that is no bound.
+        let pointers_per_block = (self.block_size / core::mem::size_of::<u32>()) as u64;
+        let addressable = Ext2Inode::NDIR_BLOCKS as u64
+            + pointers_per_block
+            + pointers_per_block.pow(2)
+            + pointers_per_block.pow(3);
+        let num_blocks = inode.size().div_ceil(self.block_size as u64);
+        if num_blocks > addressable {
+            return Err(Error::InvalidInode);
+        }
+        Ok(num_blocks as usize)
+    }
+
+    /// Returns the pointer in `i_block` leading to the given logical block of a file, and the
+    /// index to follow in each level of indirect blocks under it. Returns `None` past what the
+    /// triple indirect block maps.
+    fn block_path(&self, logical: usize) -> Option<(usize, Vec<usize>)> {
+        if logical < Ext2Inode::NDIR_BLOCKS {
+            return Some((logical, vec![]));
+        }
+
+        let pointers_per_block = self.block_size / core::mem::size_of::<u32>();
+        let mut remaining = logical - Ext2Inode::NDIR_BLOCKS;
+        let mut depth = 1;
+        let mut mapped = pointers_per_block;
+        while remaining >= mapped {
+            remaining -= mapped;
+            depth += 1;
+            if depth > 3 {
+                return None;
+            }
+            mapped *= pointers_per_block;
+        }
+
+        let mut path = vec![];
+        for _ in 0..depth {
+            mapped /= pointers_per_block;
+            path.push(remaining / mapped % pointers_per_block);
+        }
+        Some((Ext2Inode::NDIR_BLOCKS + depth - 1, path))
+    }
+
+    /// Returns the physical block backing the given logical block of the file, 0 meaning a hole.
+    /// Only the indirect blocks leading to it are read. `leaf` keeps the last indirect block of
+    /// data block pointers read, with the first logical block it maps, so that consecutive
+    /// blocks are looked up without reading the indirect blocks again.
+    fn map_block(
+        &self,
+        inode: &Inode,
+        logical: usize,
+        leaf: &mut Option<(usize, Vec<u8>)>,
+    ) -> Result<u32, Error> {
+        let (slot, path) = self.block_path(logical).ok_or(Error::InvalidInode)?;
+        let pointer = |data: &[u8], index: usize| {
+            u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap())
+        };
+
+        let block = match path.last() {
+            None => inode.block_pointers()[slot],
+            Some(&index) => {
+                let first = logical - index;
+                match leaf {
+                    Some((leaf_first, data)) if *leaf_first == first => pointer(data, index),
+                    _ => {
+                        let mut block = inode.block_pointers()[slot];
+                        for (level, &index) in path.iter().enumerate() {
+                            if block == 0 {
+                                // A missing indirect block is a hole for everything under it
+                                return Ok(0);
+                            }
+                            if block as usize >= self.num_blocks() {
+                                return Err(Error::CorruptedInode);
+                            }
+
+                            let data = self.read_fs_blocks(block as usize, 1)?;
+                            block = pointer(&data, index);
+                            if level + 1 == path.len() {
+                                *leaf = Some((first, data));
+                            }
+                        }
+                        block
+                    }
+                }
+            }
+        };
+
+        if block as usize >= self.num_blocks() {
+            return Err(Error::CorruptedInode);
+        }
+        Ok(block)
+    }
+
+    /// Returns the runs of physically contiguous blocks backing the file, in logical order.
+    /// Holes are left out, so they take no memory however large the file claims to be.
+    fn block_map(&self, inode: &Inode) -> Result<Vec<BlockRun>, Error> {
+        let mut map = BlockMap {
+            runs: vec![],
+            next: 0,
+            len: self.file_blocks(inode)?,
+            mapped: 0,
+            blocks_count: self.num_blocks(),
+        };
+        let pointers = inode.block_pointers();
+
+        for &block in &pointers[..Ext2Inode::NDIR_BLOCKS] {
+            if map.is_complete() {
+                break;
+            }
+            map.push(block)?;
+        }
+        for (depth, &block) in pointers[Ext2Inode::NDIR_BLOCKS..].iter().enumerate() {
+            self.map_indirect_block(block, depth as u32 + 1, &mut map)?;
+        }
+        Ok(map.runs)
+    }
+
+    /// Adds the blocks mapped by an indirect block of the given depth to `map`, stopping once
+    /// the whole file is mapped
+    fn map_indirect_block(&self, block: u32, depth: u32, map: &mut BlockMap) -> Result<(), Error> {
+        if map.is_complete() {
+            return Ok(());
+        }
+
+        let pointers_per_block = self.block_size / core::mem::size_of::<u32>();
+        if block == 0 {
+            // The whole range mapped by a missing indirect block is a hole
+            map.skip(pointers_per_block.saturating_pow(depth));
+            return Ok(());
+        }
+
+        if block as usize >= self.num_blocks() {
+            return Err(Error::CorruptedInode);
+        }
+
+        let data = self.read_fs_blocks(block as usize, 1)?;
+        for pointer in data.chunks_exact(core::mem::size_of::<u32>()) {
+            if map.is_complete() {
+                break;
+            }
+
+            let pointer = u32::from_le_bytes(pointer.try_into().unwrap());
+            if depth == 1 {
+                map.push(pointer)?;
+            } else {
+                self.map_indirect_block(pointer, depth - 1, map)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the size of an on-disk inode structure
+    fn inode_size(&self) -> usize {
+        if self.superblock.s_rev_level >= EXT2_DYNAMIC_REV {
+            self.superblock.s_inode_size as usize
+        } else {
+            Self::GOOD_OLD_INODE_SIZE
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn num_block_groups(&self) -> usize {
+        self.num_block_groups
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.superblock.s_blocks_count as usize
+    }
+
+    pub fn free_blocks_count(&self) -> u32 {
+        self.superblock.s_free_blocks_count
+    }
+
+    /// Returns the number of blocks reserved for the super user
+    pub fn reserved_blocks_count(&self) -> u32 {
+        self.superblock.s_r_blocks_count
+    }
+
+    pub fn inodes_count(&self) -> u32 {
+        self.superblock.s_inodes_count
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        self.superblock.s_free_inodes_count
+    }
+
+    /// Returns the revision level. Revision 0 filesystems have fixed 128 byte inodes and lack
+    /// the extended superblock fields.
+    pub fn revision_level(&self) -> u32 {
+        self.superblock.s_rev_level
+    }
+
+    pub fn minor_revision_level(&self) -> u16 {
+        self.superblock.s_minor_rev_level
+    }
+
+    /// Returns the number of times the filesystem was mounted since it was last checked
+    pub fn mount_count(&self) -> u16 {
+        self.superblock.s_mnt_count
+    }
+
+    /// Returns the number of mounts after which the filesystem should be checked, or `None` if
+    /// the check based on the mount count is disabled
+    pub fn max_mount_count(&self) -> Option<u16> {
+        // The field is signed on disk, 0 and negative values disable the check
+        let max_mount_count = self.superblock.s_max_mnt_count as i16;
+        if max_mount_count <= 0 {
+            return None;
+        }
+        Some(max_mount_count as u16)
+    }
+
+    /// Returns the time the filesystem was last mounted, in seconds since the Unix epoch, if
+    /// ever
+    pub fn last_mount_time(&self) -> Option<u64> {
+        epoch_seconds(self.superblock.s_mtime, self.superblock.s_mtime_hi)
+    }
+
+    /// Returns the time the superblock was last written, in seconds since the Unix epoch, if
+    /// ever
+    pub fn last_write_time(&self) -> Option<u64> {
+        epoch_seconds(self.superblock.s_wtime, self.superblock.s_wtime_hi)
+    }
+
+    /// Returns the time of the last filesystem check, in seconds since the Unix epoch, if ever
+    pub fn last_check_time(&self) -> Option<u64> {
+        epoch_seconds(self.superblock.s_lastcheck, self.superblock.s_lastcheck_hi)
+    }
+
+    /// Returns the time the filesystem was last mounted, if ever
+    #[cfg(feature = "std")]
+    pub fn last_mount_system_time(&self) -> Option<std::time::SystemTime> {
+        self.last_mount_time().map(system_time)
+    }
+
+    /// Returns the time the superblock was last written, if ever
+    #[cfg(feature = "std")]
+    pub fn last_write_system_time(&self) -> Option<std::time::SystemTime> {
+        self.last_write_time().map(system_time)
+    }
+
+    /// Returns the time of the last filesystem check, if ever
+    #[cfg(feature = "std")]
+    pub fn last_check_system_time(&self) -> Option<std::time::SystemTime> {
+        self.last_check_time().map(system_time)
+    }
+
+    pub fn compat_features(&self) -> CompatFeatures {
+        self.superblock.compat_features()
+    }
+
+    pub fn incompat_features(&self) -> IncompatFeatures {
+        self.superblock.incompat_features()
+    }
+
+    pub fn ro_compat_features(&self) -> RoCompatFeatures {
+        self.superblock.ro_compat_features()
+    }
+
+    /// Returns true if the filesystem was cleanly unmounted
+    pub fn is_clean(&self) -> bool {
+        (self.superblock.s_state & EXT2_VALID_FS) != 0
+    }
+
+    /// Returns true if errors have been detected on the filesystem
+    pub fn has_errors(&self) -> bool {
+        (self.superblock.s_state & EXT2_ERROR_FS) != 0
+    }
+
+    /// Returns true if the journal contains transactions that haven't been replayed
+    pub fn needs_recovery(&self) -> bool {
+        self.superblock
+            .incompat_features()
+            .contains(IncompatFeatures::RECOVER)
+    }
+
+    /// Returns true if the filesystem can only be read. This is the case when it uses read-only
+    /// compatible features that aren't implemented, or when its journal needs to be replayed.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails with `Error::ReadOnly` if the filesystem must not be modified
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Summarizes the filesystem state, reporting the most severe condition found
+    pub fn mount_state(&self) -> MountState {
+        if self.needs_recovery() {
+            MountState::NeedsRecovery
+        } else if self.has_errors() {
+            MountState::HasErrors
+        } else if !self.is_clean() {
+            MountState::Dirty
+        } else {
+            MountState::Clean
+        }
+    }
+
+    /// Returns the percentage of blocks reserved for the super user
+    pub fn reserved_percentage(&self) -> f64 {
+        let superblock = &self.superblock;
+        if superblock.s_blocks_count == 0 {
+            return 0.0;
+        }
+        superblock.s_r_blocks_count as f64 / superblock.s_blocks_count as f64 * 100.0
+    }
+
+    /// Sets the number of blocks reserved for the super user and writes the primary and backup
+    /// superblocks back to the device, like `tune2fs -r` does.
+    pub fn set_reserved_blocks(&mut self, count: u32) -> Result<(), Error> {
+        self.check_writable()?;
+        if count > self.superblock.s_blocks_count {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.superblock.s_r_blocks_count = count;
+        self.write_superblock()?;
+        self.write_backup_superblocks()
+    }
+
+    /// Returns the algorithm used for metadata checksums. Only meaningful when the filesystem has
+    /// the metadata_csum feature, in which case it is always crc32c (1) for a mounted filesystem.
+    pub fn checksum_type(&self) -> u8 {
+        self.superblock.s_checksum_type
+    }
+
+    /// Returns the number of extra inode bytes that new inodes should reserve
+    pub fn want_extra_isize(&self) -> u16 {
+        self.superblock.s_want_extra_isize
+    }
+
+    /// Returns the number of extra inode bytes that all inodes are guaranteed to have
+    pub fn min_extra_isize(&self) -> u16 {
+        self.superblock.s_min_extra_isize
+    }
+
+    /// Returns the 128-bit UUID of the volume
+    pub fn uuid(&self) -> [u8; 16] {
+        self.superblock.s_uuid
+    }
+
+    /// Returns the volume label, empty if it is not set
+    pub fn volume_name(&self) -> &str {
+        nul_terminated_str(&self.superblock.s_volume_name)
+    }
+
+    /// Returns the directory where the filesystem was last mounted, empty if unknown
+    pub fn last_mounted(&self) -> &str {
+        nul_terminated_str(&self.superblock.s_last_mounted)
+    }
+
+    /// Returns the RAID stride, the number of blocks read or written to a disk before moving to
+    /// the next one. 0 if not set.
+    pub fn raid_stride(&self) -> u16 {
+        self.superblock.s_raid_stride
+    }
+
+    /// Returns the RAID stripe width, the number of blocks in a full stripe across all data
+    /// disks. 0 if not set.
+    pub fn raid_stripe_width(&self) -> u32 {
+        self.superblock.s_raid_stripe_width
+    }
+
+    /// Returns the number of blocks used by filesystem metadata, as recorded by mkfs. Older
+    /// filesystems leave this as 0, meaning the overhead has to be computed instead.
+    pub fn overhead_blocks(&self) -> u64 {
+        self.superblock.s_overhead_clusters as u64
+    }
+
+    /// Returns the space usage of the filesystem. The blocks used by metadata are only
+    /// subtracted from the total when mkfs recorded them in `overhead_blocks`.
+    pub fn statfs(&self) -> FsStats {
+        let free_blocks = self.free_blocks_count() as u64;
+        FsStats {
+            block_size: self.block_size,
+            blocks: (self.num_blocks() as u64).saturating_sub(self.overhead_blocks()),
+            free_blocks,
+            available_blocks: free_blocks.saturating_sub(self.reserved_blocks_count() as u64),
+            inodes: self.inodes_count() as u64,
+            free_inodes: self.free_inodes_count() as u64,
+        }
+    }
+
+    /// Returns the active snapshot, if the filesystem has one
+    pub fn snapshot_info(&self) -> Option<SnapshotInfo> {
+        let superblock = self.extended_superblock()?;
+        if superblock.s_snapshot_inum == 0 {
+            return None;
+        }
+
+        Some(SnapshotInfo {
+            inode: superblock.s_snapshot_inum,
+            id: superblock.s_snapshot_id,
+            reserved_blocks: superblock.s_snapshot_r_blocks_count,
+            list_head: superblock.s_snapshot_list,
+        })
+    }
+
+    /// Returns the errors recorded in the superblock, if there are any
+    pub fn error_log(&self) -> Option<ErrorLog> {
+        let superblock = self.extended_superblock()?;
+        if superblock.s_error_count == 0 {
+            return None;
+        }
+
+        let record = |time, time_hi, inode, block, function: &[u8], line| {
+            Some(ErrorRecord {
+                // Records without a time were never filled in
+                time: epoch_seconds(time, time_hi)?,
+                inode,
+                block,
+                function: nul_terminated(function).to_vec(),
+                line,
+            })
+        };
+
+        Some(ErrorLog {
+            count: superblock.s_error_count,
+            first: record(
+                superblock.s_first_error_time,
+                superblock.s_first_error_time_hi,
+                superblock.s_first_error_ino,
+                superblock.s_first_error_block,
+                &superblock.s_first_error_func,
+                superblock.s_first_error_line,
+            ),
+            last: record(
+                superblock.s_last_error_time,
+                superblock.s_last_error_time_hi,
+                superblock.s_last_error_ino,
+                superblock.s_last_error_block,
+                &superblock.s_last_error_func,
+                superblock.s_last_error_line,
+            ),
+        })
+    }
+
+    /// Returns the default mount options string set with `tune2fs -E mount_opts`, if any
+    pub fn mount_opts_string(&self) -> Option<String> {
+        let superblock = self.extended_superblock()?;
+        let mount_opts = nul_terminated(&superblock.s_mount_opts);
+        if mount_opts.is_empty() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(mount_opts).into_owned())
+    }
+
+    /// Returns the inode holding the quota file of the given kind, if the filesystem tracks it
+    pub fn quota_inode(&self, kind: QuotaKind) -> Option<u32> {
+        let superblock = self.extended_superblock()?;
+        if !superblock
+            .ro_compat_features()
+            .contains(RoCompatFeatures::QUOTA)
+        {
+            return None;
+        }
+
+        let inode = match kind {
+            QuotaKind::User => superblock.s_usr_quota_inum,
+            QuotaKind::Group => superblock.s_grp_quota_inum,
+            QuotaKind::Project => superblock.s_prj_quota_inum,
+        };
+        Some(inode).filter(|&inode| inode != 0)
+    }
+
+    /// Returns the superblock only if it is recent enough to contain the extended fields
+    fn extended_superblock(&self) -> Option<&Ext2SuperBlock> {
+        Some(&self.superblock).filter(|superblock| superblock.s_rev_level >= EXT2_DYNAMIC_REV)
+    }
+
+    /// Returns true if the given block group holds a copy of the superblock and the group
+    /// descriptor table. With sparse_super only groups 0, 1 and powers of 3, 5 and 7 do, and
+    /// with sparse_super2 only group 0 and the (up to two) groups in `s_backup_bgs`.
+    pub fn group_has_superblock(&self, group: usize) -> bool {
+        if group >= self.num_block_groups {
+            return false;
+        }
+        if group == 0 {
+            return true;
+        }
+
+        if self
+            .superblock
+            .compat_features()
+            .contains(CompatFeatures::SPARSE_SUPER2)
+        {
+            return self
+                .superblock
+                .s_backup_bgs
+                .iter()
+                .any(|&backup| backup as usize == group);
+        }
+
+        if !self
+            .superblock
+            .ro_compat_features()
+            .contains(RoCompatFeatures::SPARSE_SUPER)
+        {
+            return true;
+        }
+
+        let is_power_of = |base: usize| {
+            let mut value = base;
+            while value < group {
+                value *= base;
+            }
+            value == group
+        };
+        group <= 1 || is_power_of(3) || is_power_of(5) || is_power_of(7)
+    }
+
+    /// Returns the total number of blocks reserved across all groups for growing the group
+    /// descriptor table
+    pub fn total_reserved_gdt_blocks(&self) -> u64 {
+        let reserved_per_group = self.superblock.s_reserved_gdt_blocks as u64;
+
+        let groups_with_gdt = (0..self.num_block_groups)
+            .filter(|&group| self.group_has_superblock(group))
+            .count() as u64;
+        reserved_per_group * groups_with_gdt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FileDevice {
+        data: Vec<u8>,
+        reads: AtomicUsize,
+    }
+
+    impl FileDevice {
+        const BLOCK_SIZE: usize = 1024;
+
+        fn new(path: &std::path::Path) -> Self {
+            let mut file = std::fs::File::open(path).unwrap();
+            let mut dev = FileDevice {
+                data: vec![],
+                reads: AtomicUsize::new(0),
+            };
+            file.read_to_end(&mut dev.data).unwrap();
+            dev
+        }
+
+        /// Patches the raw image at the given byte offset, used to craft corrupted filesystems
+        fn patch(&mut self, offset: usize, bytes: &[u8]) {
+            self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    impl BlockDevice for FileDevice {
+        type Error = ();
+
+        fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, ()> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            // Reads past the end of the image are short
+            let start = (index * FileDevice::BLOCK_SIZE).min(self.data.len());
+            let end = (start + FileDevice::BLOCK_SIZE * num_blocks).min(self.data.len());
+            Ok(self.data[start..end].to_vec())
+        }
+
+        fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), ()> {
+            let offset = index * FileDevice::BLOCK_SIZE;
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn get_block_size(&self) -> usize {
+            FileDevice::BLOCK_SIZE
+        }
+    }
+
+    /// Device that fails every read touching blocks at or past `first_bad_block`
+    struct FailingDevice {
+        inner: FileDevice,
+        first_bad_block: usize,
+    }
+
+    impl BlockDevice for FailingDevice {
+        type Error = &'static str;
+
+        fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, Self::Error> {
+            if index + num_blocks > self.first_bad_block {
+                return Err("bad block");
+            }
+            Ok(self.inner.read_blocks(index, num_blocks).unwrap())
+        }
+
+        fn write_blocks(&mut self, _index: usize, _data: &[u8]) -> Result<(), Self::Error> {
+            Err("read-only device")
+        }
+
+        fn get_block_size(&self) -> usize {
+            self.inner.get_block_size()
+        }
+    }
+
+    #[test]
+    fn read_superblock() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let superblock = &ext2fs.superblock;
+        assert_eq!(superblock.s_magic, 0xEF53);
+        assert_eq!(superblock.s_creator_os, 0);
+        assert_eq!(superblock.s_state, 1);
+        assert_eq!(superblock.s_blocks_per_group, 32768); // 32768 blocks per group
+        assert_eq!(superblock.s_log_block_size, 2); // 4096 bytes/ block
+        assert_eq!(superblock.s_block_group_nr, 0);
+
+        assert_eq!(ext2fs.block_size(), 4096);
+        assert_eq!(ext2fs.num_block_groups(), 1);
+        assert_eq!(ext2fs.num_blocks(), 256);
+    }
+
+    #[test]
+    fn superblock_metadata() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.inodes_count(), 128);
+        assert_eq!(ext2fs.free_inodes_count(), 117);
+        assert_eq!(ext2fs.free_blocks_count(), 242);
+        assert_eq!(ext2fs.reserved_blocks_count(), 12);
+        assert_eq!(ext2fs.revision_level(), EXT2_DYNAMIC_REV);
+        assert_eq!(ext2fs.minor_revision_level(), 0);
+        assert_eq!(ext2fs.mount_count(), 0);
+        assert_eq!(ext2fs.max_mount_count(), None);
+        assert_eq!(ext2fs.last_mount_time(), None);
+        assert_eq!(ext2fs.last_write_time(), Some(1623268620));
+        assert_eq!(ext2fs.last_check_time(), Some(1623268620));
+        let written = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1623268620);
+        assert_eq!(ext2fs.last_mount_system_time(), None);
+        assert_eq!(ext2fs.last_write_system_time(), Some(written));
+        assert_eq!(ext2fs.last_check_system_time(), Some(written));
+
+        assert_eq!(
+            ext2fs.compat_features(),
+            CompatFeatures::EXT_ATTR | CompatFeatures::RESIZE_INODE | CompatFeatures::DIR_INDEX
+        );
+        assert_eq!(ext2fs.incompat_features(), IncompatFeatures::FILETYPE);
+        assert_eq!(
+            ext2fs.ro_compat_features(),
+            RoCompatFeatures::SPARSE_SUPER | RoCompatFeatures::LARGE_FILE
+        );
+        assert_eq!(
+            format!("{:?}", ext2fs.ro_compat_features()),
+            "RoCompatFeatures(SPARSE_SUPER | LARGE_FILE)"
+        );
+
+        let mut dev = ext2fs.device;
+        dev.patch(1024 + 0x34, &3u16.to_le_bytes()); // s_mnt_count
+        dev.patch(1024 + 0x36, &20u16.to_le_bytes()); // s_max_mnt_count
+        dev.patch(1024 + 0x5C, &0x8038u32.to_le_bytes()); // Unknown compat bit
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.mount_count(), 3);
+        assert_eq!(ext2fs.max_mount_count(), Some(20));
+        assert_eq!(
+            ext2fs.compat_features().unknown(),
+            CompatFeatures::from_bits(0x8000)
+        );
+        assert_eq!(
+            format!("{:?}", ext2fs.compat_features()),
+            "CompatFeatures(EXT_ATTR | RESIZE_INODE | DIR_INDEX | 0x8000)"
+        );
+    }
+
+    #[test]
+    fn read_extended_superblock_fields() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x78, b"r\xC3\xA9sum\xC3\0");
+        dev.patch(1024 + 0x88, b"/mnt/data\0");
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let superblock = &ext2fs.superblock;
+        assert_eq!(superblock.s_first_ino, 11);
+        assert_eq!(superblock.s_inode_size, 128);
+        assert_eq!(superblock.s_def_hash_version, 1); // half_md4
+        assert_eq!(superblock.s_default_mount_opts, 0x000C); // user_xattr acl
+        assert_eq!(superblock.s_mkfs_time, 0x60c11d0c);
+        assert_eq!(
+            ext2fs.uuid(),
+            [
+                0x7e, 0xf5, 0x92, 0x9b, 0xe0, 0xad, 0x4e, 0x63, 0x98, 0x3e, 0xfa, 0x57, 0x7a, 0x93,
+                0xb9, 0x20
+            ]
+        );
+        // The truncated UTF-8 sequence at the end is dropped
+        assert_eq!(ext2fs.volume_name(), "résum");
+        assert_eq!(probe(&ext2fs.device).unwrap().label, "résum");
+        assert_eq!(ext2fs.last_mounted(), "/mnt/data");
+    }
+
+    #[test]
+    fn cached_filesystem() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::with_cache(dev, 8).unwrap();
+
+        assert_eq!(ext2fs.block_size(), 4096);
+        assert_eq!(ext2fs.num_blocks(), 256);
+    }
+
+    #[test]
+    fn repeated_reads_are_served_from_cache() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::with_write_back_cache(dev, 64).unwrap();
+
+        let list = |ext2fs: &Ext2Fs<CachingDevice<FileDevice>>| -> Vec<DirEntry> {
+            let dir = ext2fs.lookup("/dir/many").unwrap();
+            ext2fs.read_dir(&dir).unwrap().map(Result::unwrap).collect()
+        };
+        let first = list(&ext2fs);
+        let reads = ext2fs.device.inner().reads.load(Ordering::Relaxed);
+        assert_eq!(list(&ext2fs), first);
+        assert_eq!(ext2fs.device.inner().reads.load(Ordering::Relaxed), reads);
+    }
+
+    #[test]
+    fn flushing_a_file_writes_back_the_cache() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let mut ext2fs = Ext2Fs::with_write_back_cache(FileDevice::new(&path), 1024).unwrap();
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        ext2fs.create_file(&root, "flushed.txt").unwrap();
+        ext2fs.flush().unwrap();
+        let flushed = ext2fs.device.inner().data.clone();
+
+        ext2fs
+            .open_mut("/flushed.txt")
+            .unwrap()
+            .write_all(b"flushed\n")
+            .unwrap();
+        assert!(ext2fs.device.inner().data == flushed);
+
+        ext2fs.open_mut("/flushed.txt").unwrap().flush().unwrap();
+        assert!(ext2fs.device.inner().data != flushed);
+        let dev = FileDevice {
+            data: ext2fs.device.inner().data.clone(),
+            reads: AtomicUsize::new(0),
+        };
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let file = ext2fs.lookup("/flushed.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), b"flushed\n");
+    }
+
+    #[test]
+    fn cached_writes_reach_the_device_on_flush() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let original = dev.data.clone();
+        let mut ext2fs = Ext2Fs::with_write_back_cache(dev, 1024).unwrap();
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut file = ext2fs.create_file(&root, "cached.txt").unwrap();
+        ext2fs.write_file(&mut file, 0, b"cached\n").unwrap();
+
+        // Everything is still in memory, but reads already see it
+        assert!(ext2fs.device.inner().data == original);
+        let file = ext2fs.lookup("/cached.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), b"cached\n");
+
+        ext2fs.flush().unwrap();
+        let dev = ext2fs.device.into_inner().unwrap();
+        assert!(dev.data != original);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let file = ext2fs.lookup("/cached.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), b"cached\n");
+    }
+
+    #[test]
+    fn device_errors_are_propagated() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FailingDevice {
+            inner: FileDevice::new(&path),
+            first_bad_block: 1,
+        };
+        match Ext2Fs::new(dev) {
+            Err(Error::DeviceError(error)) => assert_eq!(format!("{:?}", error), "\"bad block\""),
+            _ => panic!("Expected a device error"),
+        }
+
+        // Everything past the inode table is unreadable
+        let dev = FailingDevice {
+            inner: FileDevice::new(&path),
+            first_bad_block: 100,
+        };
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let big = ext2fs.read_inode(12).unwrap();
+        assert!(matches!(ext2fs.read_file(&big), Err(Error::DeviceError(_))));
+        let root = ext2fs.read_inode(2).unwrap();
+        let mut entries = ext2fs.read_dir(&root).unwrap();
+        assert!(matches!(entries.next(), Some(Err(Error::DeviceError(_)))));
+        assert!(entries.next().is_none());
+        assert!(matches!(
+            ext2fs.set_reserved_blocks(0),
+            Err(Error::DeviceError(_))
+        ));
+    }
+
+    #[test]
+    fn short_reads_are_detected() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.data.truncate(1500);
+        assert!(matches!(Ext2Fs::new(dev), Err(Error::ShortRead)));
+
+        // The superblock and descriptors are intact, but the root directory block is missing
+        let mut dev = FileDevice::new(&path);
+        dev.data.truncate(8 * 4096);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let root = ext2fs.read_inode(2).unwrap();
+        assert!(matches!(ext2fs.read_file(&root), Err(Error::ShortRead)));
+    }
+
+    #[test]
+    fn superblock_layout() {
+        assert_eq!(Ext2SuperBlock::SIZE, 1024);
+        assert_eq!(Ext2GroupDescriptor::SIZE, 32);
+        assert_eq!(Ext2Inode::SIZE, 128);
+    }
+
+    #[test]
+    fn read_group_descriptors() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let descriptor = ext2fs.group_descriptor(0).unwrap();
+        assert_eq!(descriptor.block_bitmap(), 2);
+        assert_eq!(descriptor.inode_bitmap(), 3);
+        assert_eq!(descriptor.inode_table(), 4);
+        assert_eq!(descriptor.free_blocks_count(), 242);
+        assert_eq!(descriptor.free_inodes_count(), 117);
+        assert_eq!(descriptor.used_dirs_count(), 2);
+        assert!(matches!(
+            ext2fs.group_descriptor(1),
+            Err(Error::InvalidBlockGroup)
+        ));
+    }
+
+    #[test]
+    fn read_inode() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(2).unwrap();
+        assert_eq!(root.number(), 2);
+        assert_eq!(root.file_type(), FileType::Directory);
+        assert_eq!(root.permissions(), 0o755);
+        assert_eq!(root.size(), 4096);
+        assert_eq!(root.links_count(), 3);
+        assert_eq!(root.uid(), 0);
+        assert_eq!(root.gid(), 0);
+        assert_eq!(root.mtime(), 0x60c11d0c);
+        assert_eq!(root.sector_count(), 8);
+        assert_eq!(root.block_pointers()[0], 8);
+
+        let lost_and_found = ext2fs.read_inode(11).unwrap();
+        assert_eq!(lost_and_found.file_type(), FileType::Directory);
+        assert_eq!(lost_and_found.permissions(), 0o700);
+        assert_eq!(lost_and_found.size(), 16384);
+        assert_eq!(&lost_and_found.block_pointers()[..5], &[9, 10, 11, 12, 0]);
+
+        assert!(matches!(
+            ext2fs.read_inode(0).unwrap_err(),
+            Error::InvalidInode
+        ));
+        assert!(matches!(
+            ext2fs.read_inode(129).unwrap_err(),
+            Error::InvalidInode
+        ));
+    }
+
+    #[test]
+    fn read_file() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let hello = ext2fs.read_inode(122).unwrap();
+        assert_eq!(ext2fs.read_file(&hello).unwrap(), b"Hello, world!\n");
+
+        // Uses single and double indirect blocks
+        let big = ext2fs.read_inode(12).unwrap();
+        let expected: Vec<u8> = (0..300000).map(|i| ((i * 7) % 251) as u8).collect();
+        assert_eq!(ext2fs.read_file(&big).unwrap(), expected);
+
+        let sparse = ext2fs.read_inode(127).unwrap();
+        let data = ext2fs.read_file(&sparse).unwrap();
+        assert_eq!(data.len(), 102401);
+        assert_eq!(data[0], b'A');
+        assert!(data[1..102400].iter().all(|&b| b == 0));
+        assert_eq!(data[102400], b'B');
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn read_file_parallel() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        for ino in [12, 122, 127] {
+            let inode = ext2fs.read_inode(ino).unwrap();
+            assert_eq!(
+                ext2fs.read_file_parallel(&inode).unwrap(),
+                ext2fs.read_file(&inode).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn read_root_dir() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(2).unwrap();
+        let entries: Vec<DirEntry> = ext2fs
+            .read_dir(&root)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = [
+            (2, ".", FileType::Directory),
+            (2, "..", FileType::Directory),
+            (11, "lost+found", FileType::Directory),
+        ];
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (inode, name, file_type)) in entries.iter().zip(expected.iter()) {
+            assert_eq!(entry.inode, *inode);
+            assert_eq!(entry.name, *name);
+            assert_eq!(entry.file_type, *file_type);
+        }
+    }
+
+    #[test]
+    fn read_multi_block_dir() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let find = |dir: &Inode, name: &str| {
+            ext2fs
+                .read_dir(dir)
+                .unwrap()
+                .map(Result::unwrap)
+                .find(|entry| entry.name == name)
+                .unwrap()
+        };
+        let root = ext2fs.read_inode(2).unwrap();
+        let dir = ext2fs.read_inode(find(&root, "dir").inode).unwrap();
+        let many = ext2fs.read_inode(find(&dir, "many").inode).unwrap();
+        assert!(many.size() > ext2fs.block_size() as u64);
+
+        let mut names: Vec<String> = ext2fs
+            .read_dir(&many)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type == FileType::Regular)
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        let expected: Vec<String> = (0..100).map(|i| format!("file_{:03}", i)).collect();
+        assert_eq!(names, expected);
+
+        let hello = ext2fs.read_inode(find(&root, "hello.txt").inode).unwrap();
+        assert!(matches!(ext2fs.read_dir(&hello), Err(Error::NotADirectory)));
+    }
+
+    #[test]
+    fn lookup() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.lookup("/").unwrap().number(), 2);
+        assert_eq!(ext2fs.lookup("/hello.txt").unwrap().number(), 122);
+        let nested = ext2fs.lookup("/dir/sub/deep.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&nested).unwrap(), b"deep\n");
+        assert_eq!(
+            ext2fs
+                .lookup("//dir/./sub/../..//hello.txt")
+                .unwrap()
+                .number(),
+            122
+        );
+        assert_eq!(
+            ext2fs.lookup("/dir/sub/").unwrap().file_type(),
+            FileType::Directory
+        );
+        assert_eq!(ext2fs.lookup("/..").unwrap().number(), 2);
+
+        match ext2fs.lookup("/dir/missing/deep.txt") {
+            Err(Error::NotFound(component)) => assert_eq!(component, "missing"),
+            _ => panic!("Expected NotFound"),
+        }
+        assert!(matches!(
+            ext2fs.lookup("/hello.txt/foo").unwrap_err(),
+            Error::NotADirectory
+        ));
+
+        // Relative paths have no directory to start from
+        for relative in ["hello.txt", "dir/nested.txt", "./hello.txt", ""] {
+            assert!(matches!(ext2fs.lookup(relative), Err(Error::InvalidPath)));
+            assert!(matches!(ext2fs.resolve(relative), Err(Error::InvalidPath)));
+            assert!(matches!(ext2fs.open(relative), Err(Error::InvalidPath)));
+        }
+    }
+
+    #[test]
+    fn open_file() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let file = ext2fs.open("/dir/nested.txt").unwrap();
+        assert_eq!(file.size(), 7);
+        assert_eq!(file.read_all().unwrap(), b"nested\n");
+        assert_eq!(ext2fs.read_file(file.inode()).unwrap(), b"nested\n");
+    }
+
+    #[test]
+    fn read_and_seek_file() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let mut file = ext2fs.open("/big.bin").unwrap();
+        let mut contents = vec![];
+        std::io::copy(&mut file, &mut contents).unwrap();
+        assert_eq!(contents, ext2fs.read_file(file.inode()).unwrap());
+        assert_eq!(contents.len(), 300_000);
+
+        let mut tail = [0; 8];
+        assert_eq!(file.seek(std::io::SeekFrom::End(-5)).unwrap(), 299_995);
+        assert_eq!(file.read(&mut tail).unwrap(), 5);
+        assert_eq!(tail[..5], contents[299_995..]);
+        assert_eq!(file.read(&mut tail).unwrap(), 0);
+
+        // Seeking past the end is allowed, reads there return nothing
+        assert_eq!(file.seek(std::io::SeekFrom::Current(10)).unwrap(), 300_010);
+        assert_eq!(file.read(&mut tail).unwrap(), 0);
+        assert!(file.seek(std::io::SeekFrom::End(-300_001)).is_err());
+        assert_eq!(file.seek(std::io::SeekFrom::Start(1023)).unwrap(), 1023);
+        assert_eq!(file.read(&mut tail).unwrap(), 1);
+        assert_eq!(tail[0], contents[1023]);
+
+        // Holes read as zeros
+        let mut sparse = ext2fs.open("/sparse.bin").unwrap();
+        let mut contents = vec![];
+        sparse.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len(), 102_401);
+        assert_eq!(contents[0], b'A');
+        assert!(contents[1..102_400].iter().all(|&byte| byte == 0));
+        assert_eq!(contents[102_400], b'B');
+    }
+
+    #[test]
+    fn reject_oversized_files() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let ino = ext2fs.lookup("/hello.txt").unwrap().number();
+        let (block, offset) = ext2fs.inode_location(ino).unwrap();
+        let offset = block * 1024 + offset;
+
+        // 32GiB is more than 1KiB blocks can map
+        let mut dev = FileDevice::new(&path);
+        dev.patch(offset + 0x6C, &8u32.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let inode = ext2fs.read_inode(ino).unwrap();
+        assert_eq!(inode.size(), (8u64 << 32) + 14);
+        assert!(matches!(ext2fs.read_file(&inode), Err(Error::InvalidInode)));
+        assert!(ext2fs
+            .open("/hello.txt")
+            .unwrap()
+            .read(&mut [0; 4])
+            .is_err());
+    }
+
+    #[test]
+    fn block_maps_leave_holes_out() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let ino = ext2fs.lookup("/hello.txt").unwrap().number();
+        let (block, offset) = ext2fs.inode_location(ino).unwrap();
+        let offset = block * 1024 + offset;
+
+        // 12GiB, almost all of it a hole after the first block
+        let mut dev = FileDevice::new(&path);
+        dev.patch(offset + 0x6C, &3u32.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let inode = ext2fs.read_inode(ino).unwrap();
+        let runs = ext2fs.block_map(&inode).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!((runs[0].logical, runs[0].len), (0, 1));
+
+        let mut data = [0; 14];
+        ext2fs
+            .open("/hello.txt")
+            .unwrap()
+            .read_exact(&mut data)
+            .unwrap();
+        assert_eq!(&data, b"Hello, world!\n");
+    }
+
+    #[test]
+    fn sparse_files_larger_than_the_filesystem() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        // 3GiB, in a 4MiB filesystem
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut file = ext2fs.create_file(&root, "huge.bin").unwrap();
+        ext2fs.write_file(&mut file, (3 << 30) - 3, b"end").unwrap();
+
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        let mut file = ext2fs.open("/huge.bin").unwrap();
+        assert_eq!(
+            file.seek(std::io::SeekFrom::End(-4)).unwrap(),
+            (3 << 30) - 4
+        );
+        let mut data = vec![];
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"\0end");
+    }
+
+    #[test]
+    fn reads_only_map_the_block_under_the_cursor() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let expected: Vec<u8> = (0..300000).map(|i| ((i * 7) % 251) as u8).collect();
+
+        // The last block is under the double indirect block, which is read with the indirect
+        // block under it and the data block
+        let mut file = ext2fs.open("/big.bin").unwrap();
+        let reads = ext2fs.device.reads.load(Ordering::Relaxed);
+        file.seek(std::io::SeekFrom::End(-1)).unwrap();
+        let mut byte = [0];
+        file.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], expected[299999]);
+        assert_eq!(ext2fs.device.reads.load(Ordering::Relaxed) - reads, 3);
+
+        // The indirect block is kept for the blocks next to it
+        let reads = ext2fs.device.reads.load(Ordering::Relaxed);
+        file.seek(std::io::SeekFrom::Current(-2000)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], expected[298000]);
+        assert_eq!(ext2fs.device.reads.load(Ordering::Relaxed) - reads, 1);
+
+        file.seek(std::io::SeekFrom::Start(0)).unwrap();
+        let mut data = vec![];
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn small_reads_are_buffered() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let mut file = ext2fs.open("/hello.txt").unwrap();
+        let reads = ext2fs.device.reads.load(Ordering::Relaxed);
+        let mut contents = vec![];
+        let mut byte = [0];
+        while file.read(&mut byte).unwrap() == 1 {
+            contents.push(byte[0]);
+        }
+        assert_eq!(contents, b"Hello, world!\n");
+        assert_eq!(ext2fs.device.reads.load(Ordering::Relaxed) - reads, 1);
+    }
+
+    #[test]
+    fn write_through_file_handle() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        ext2fs.create_file(&root, "copy.bin").unwrap();
+
+        let mut original = vec![];
+        ext2fs
+            .open("/big.bin")
+            .unwrap()
+            .read_to_end(&mut original)
+            .unwrap();
+
+        let mut file = ext2fs.open_mut("/copy.bin").unwrap();
+        std::io::copy(&mut &original[..], &mut file).unwrap();
+        file.seek(std::io::SeekFrom::Start(10)).unwrap();
+        file.write_all(b"0123").unwrap();
+        file.seek(std::io::SeekFrom::Current(-6)).unwrap();
+        let mut data = [0; 8];
+        file.read_exact(&mut data).unwrap();
+        assert_eq!(&data[..2], &original[8..10]);
+        assert_eq!(&data[2..6], b"0123");
+        assert_eq!(file.size(), 300_000);
+
+        original[10..14].copy_from_slice(b"0123");
+        let copy = ext2fs.open("/copy.bin").unwrap();
+        assert_eq!(copy.read_all().unwrap(), original);
+
+        let dir = ext2fs.open_mut("/dir");
+        let error = dir.unwrap().write(b"x").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::IsADirectory);
+        assert!(matches!(
+            error.into_inner().unwrap().downcast::<Error>().as_deref(),
+            Ok(Error::IsADirectory)
+        ));
+    }
+
+    #[test]
+    fn short_writes_when_running_out_of_space() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        ext2fs.create_file(&root, "fill.bin").unwrap();
+
+        // Far more than the image can hold, what fits is reported as written
+        let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 249) as u8).collect();
+        let mut file = ext2fs.open_mut("/fill.bin").unwrap();
+        let written = file.write(&data).unwrap();
+        assert!(written > 0 && written < data.len());
+        assert_eq!(file.stream_position().unwrap(), written as u64);
+        assert_eq!(file.size(), written as u64);
+
+        // Nothing else fits
+        assert_eq!(
+            file.write(&data[written..]).unwrap_err().kind(),
+            std::io::ErrorKind::StorageFull
+        );
+        assert_eq!(file.stream_position().unwrap(), written as u64);
+
+        let file = ext2fs.lookup("/fill.bin").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), &data[..written]);
+    }
+
+    #[test]
+    fn directories_with_64k_blocks() {
+        let path = std::path::PathBuf::from("ext2fs_64k_blocks.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.block_size(), 65536);
+
+        // The second block of lost+found is a single unused entry spanning the whole block
+        let lost_found = ext2fs.lookup("/lost+found").unwrap();
+        assert_eq!(lost_found.size(), 2 * 65536);
+        let names: Vec<String> = ext2fs
+            .read_dir(&lost_found)
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect();
+        assert_eq!(names, vec![".", ".."]);
+
+        // Entries that older kernels stored with a record length of 0 read the same way
+        let block = ext2fs.dir_blocks(&lost_found).unwrap()[1] as usize;
+        ext2fs.device.patch(block * 65536 + 4, &0u16.to_le_bytes());
+        assert_eq!(ext2fs.read_dir(&lost_found).unwrap().count(), 2);
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        for i in 0..2 {
+            let name = format!("file_{}", i);
+            let mut file = ext2fs.create_file(&lost_found, &name).unwrap();
+            ext2fs.write_file(&mut file, 0, name.as_bytes()).unwrap();
+        }
+        ext2fs.create_file(&root, "top").unwrap();
+
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        let file = ext2fs.lookup("/lost+found/file_1").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), b"file_1");
+        assert!(ext2fs.lookup("/top").is_ok());
+
+        // New blocks are a single entry, whose length is stored as 65535
+        let mut block = vec![0; 65536];
+        dir::init_block(&mut block, 11, b"new", Some(1));
+        assert_eq!(block[4..6], 65535u16.to_le_bytes());
+        assert!(dir::insert_entry(&mut block, 12, b"next", Some(1)).unwrap());
+        assert_eq!(block[4..6], 12u16.to_le_bytes());
+        assert_eq!(block[12 + 4..12 + 6], ((65536 - 12) as u16).to_le_bytes());
+    }
+
+    #[test]
+    fn read_symlinks() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        // The target is stored in i_block
+        let link = ext2fs.read_inode(123).unwrap();
+        assert_eq!(link.file_type(), FileType::Symlink);
+        assert_eq!(link.sector_count(), 0);
+        assert_eq!(ext2fs.read_link(&link).unwrap(), "hello.txt");
+
+        // The target is too long for i_block and lives in a data block
+        let long_link = ext2fs.read_inode(124).unwrap();
+        assert_eq!(long_link.sector_count(), 2);
+        assert_eq!(
+            ext2fs.read_link(&long_link).unwrap(),
+            "dir/a_very_long_file_name_that_makes_the_symlink_target_exceed_sixty_bytes.txt"
+        );
+
+        let hello = ext2fs.read_inode(122).unwrap();
+        assert!(matches!(ext2fs.read_link(&hello), Err(Error::NotASymlink)));
+    }
+
+    #[test]
+    fn resolve_symlinks() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        // lookup doesn't follow symlinks, not even in the middle of the path
+        assert_eq!(ext2fs.lookup("/link").unwrap().number(), 123);
+        assert!(matches!(
+            ext2fs.lookup("/dir_link/nested.txt"),
+            Err(Error::NotADirectory)
+        ));
+
+        assert_eq!(ext2fs.resolve("/link").unwrap().number(), 122);
+        assert_eq!(ext2fs.resolve("/dir/up").unwrap().number(), 122);
+        assert_eq!(ext2fs.resolve("/dir_link").unwrap().number(), 13);
+        let nested = ext2fs.resolve("/dir_link/nested.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&nested).unwrap(), b"nested\n");
+        let long = ext2fs.resolve("/long_link").unwrap();
+        assert_eq!(ext2fs.read_file(&long).unwrap(), b"long\n");
+        assert_eq!(
+            ext2fs.open("/dir_link/up").unwrap().read_all().unwrap(),
+            b"Hello, world!\n"
+        );
+
+        assert!(matches!(
+            ext2fs.resolve("/loop_a"),
+            Err(Error::TooManySymlinks)
+        ));
+        assert!(matches!(
+            ext2fs.resolve("/loop_b/file"),
+            Err(Error::TooManySymlinks)
+        ));
+    }
+
+    #[test]
+    fn unknown_file_types_in_directory_entries() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        // The file type of lost+found, the third entry of the root directory
+        dev.patch(8 * 4096 + 24 + 7, &[0x42]);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(2).unwrap();
+        let entries: Vec<DirEntry> = ext2fs
+            .read_dir(&root)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].name, "lost+found");
+        assert_eq!(entries[2].file_type, FileType::Unknown(0x42));
+        assert_eq!(entries[2].file_type.to_dir_entry(), 0x42);
+        assert_eq!(
+            FileType::from_dir_entry(FileType::Unknown(9).to_dir_entry()),
+            FileType::Unknown(9)
+        );
+
+        // Strict iterators report the entry and carry on with the next one
+        let results: Vec<Result<DirEntry, Error>> =
+            ext2fs.read_dir(&root).unwrap().strict().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[..2].iter().all(Result::is_ok));
+        assert!(matches!(results[2], Err(Error::UnknownFileType(0x42))));
+    }
+
+    #[test]
+    fn file_types_without_the_filetype_feature() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x60, &0u32.to_le_bytes());
+        // Without the feature, the file type byte is the upper byte of the name length
+        for entry in [0, 12, 24] {
+            dev.patch(8 * 4096 + entry + 7, &[0]);
+        }
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(2).unwrap();
+        let entries: Vec<DirEntry> = ext2fs
+            .read_dir(&root)
+            .unwrap()
+            .strict()
+            .map(Result::unwrap)
+            .collect();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec![".", "..", "lost+found"]);
+        // The type isn't recorded, only the inode has it
+        assert!(entries
+            .iter()
+            .all(|entry| entry.file_type == FileType::Unknown(0)));
+        assert_eq!(
+            ext2fs.read_inode(entries[2].inode).unwrap().file_type(),
+            FileType::Directory
+        );
+    }
+
+    #[test]
+    fn reject_corrupted_directory() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        // Make the rec_len of the root's "." entry point past the end of the block
+        dev.patch(8 * 4096 + 4, &8192u16.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(2).unwrap();
+        let mut entries = ext2fs.read_dir(&root).unwrap();
+        assert!(matches!(
+            entries.next(),
+            Some(Err(Error::CorruptedDirectory))
+        ));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn reject_corrupted_group_descriptors() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        // Free blocks count of group 0 no longer matches the superblock
+        dev.patch(4096 + 0x0C, &241u16.to_le_bytes());
+        assert!(matches!(
+            Ext2Fs::new(dev),
+            Err(Error::CorruptedGroupDescriptors)
+        ));
+
+        let mut dev = FileDevice::new(&path);
+        // Inode table of group 0 points past the end of the filesystem
+        dev.patch(4096 + 0x08, &300u32.to_le_bytes());
+        assert!(matches!(
+            Ext2Fs::new(dev),
+            Err(Error::CorruptedGroupDescriptors)
+        ));
+
+        let mut dev = FileDevice::new(&path);
+        // More free blocks than the block bitmap can track, matched by the superblock
+        dev.patch(4096 + 0x0C, &40000u16.to_le_bytes());
+        dev.patch(1024 + 0x0C, &40000u32.to_le_bytes());
+        assert!(matches!(
+            Ext2Fs::new(dev),
+            Err(Error::CorruptedGroupDescriptors)
+        ));
+    }
+
+    #[test]
+    fn filesystem_at_offset() {
+        const PARTITION_OFFSET: usize = 1024 * 1024;
+
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut disk = vec![0; PARTITION_OFFSET];
+        disk.append(&mut dev.data);
+        disk.resize(disk.len() + PARTITION_OFFSET, 0);
+        dev.data = disk;
+
+        assert!(matches!(
+            Ext2Fs::new_at_offset(FileDevice::new(&path), 512),
+            Err(Error::InvalidArgument)
+        ));
+
+        let ext2fs = Ext2Fs::new_at_offset(dev, PARTITION_OFFSET).unwrap();
+        assert_eq!(ext2fs.block_size(), 1024);
+        assert_eq!(ext2fs.num_blocks(), 4096);
+
+        // Data blocks are read from the partition too, not from the start of the device
+        let hello = ext2fs.lookup("/hello.txt").unwrap();
+        assert_eq!(ext2fs.read_file(&hello).unwrap(), b"Hello, world!\n");
+        let big = ext2fs.lookup("/big.bin").unwrap();
+        let expected: Vec<u8> = (0..300000).map(|i| ((i * 7) % 251) as u8).collect();
+        assert_eq!(ext2fs.read_file(&big).unwrap(), expected);
+    }
+
+    #[test]
+    fn probe_filesystem() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+
+        let probe = probe(&dev).unwrap();
+        assert_eq!(
+            probe.uuid,
+            [
+                0x7e, 0xf5, 0x92, 0x9b, 0xe0, 0xad, 0x4e, 0x63, 0x98, 0x3e, 0xfa, 0x57, 0x7a, 0x93,
+                0xb9, 0x20
+            ]
+        );
+        assert_eq!(probe.label, "");
+        assert_eq!(probe.block_size, 4096);
+        assert_eq!(probe.kind, FilesystemKind::Ext2);
+    }
+
+    #[test]
+    fn probe_labelled_ext3() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x78, b"rootfs\0");
+        let compat = 0x38u32 | CompatFeatures::HAS_JOURNAL.bits();
+        dev.patch(1024 + 0x5C, &compat.to_le_bytes());
+
+        let probe = probe(&dev).unwrap();
+        assert_eq!(probe.label, "rootfs");
+        assert_eq!(probe.kind, FilesystemKind::Ext3);
+    }
+
+    #[test]
+    fn extra_isize() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x15C, &28u16.to_le_bytes());
+        dev.patch(1024 + 0x15E, &32u16.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.min_extra_isize(), 28);
+        assert_eq!(ext2fs.want_extra_isize(), 32);
+    }
+
+    #[test]
+    fn raid_geometry() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x164, &16u16.to_le_bytes());
+        dev.patch(1024 + 0x170, &64u32.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.raid_stride(), 16);
+        assert_eq!(ext2fs.raid_stripe_width(), 64);
+    }
+
+    #[test]
+    fn overhead_blocks() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.overhead_blocks(), 0);
+
+        dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x248, &14u32.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.overhead_blocks(), 14);
+    }
+
+    #[test]
+    fn statfs() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let stats = ext2fs.statfs();
+        assert_eq!(stats.block_size, 4096);
+        assert_eq!(stats.blocks, 256);
+        assert_eq!(stats.free_blocks, ext2fs.free_blocks_count() as u64);
+        assert_eq!(
+            stats.available_blocks,
+            stats.free_blocks - ext2fs.reserved_blocks_count() as u64
+        );
+        assert_eq!(stats.inodes, 128);
+        assert_eq!(stats.free_inodes, ext2fs.free_inodes_count() as u64);
+
+        // The recorded overhead only comes off the total, the free blocks are already exact
+        dev = ext2fs.device;
+        dev.patch(1024 + 0x248, &14u32.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        let with_overhead = ext2fs.statfs();
+        assert_eq!(with_overhead.blocks, 242);
+        assert_eq!(with_overhead.free_blocks, stats.free_blocks);
+        assert_eq!(with_overhead.available_blocks, stats.available_blocks);
+    }
+
+    #[test]
+    fn reserved_gdt_blocks() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        // Split the 256 blocks into 8 groups of 32 blocks, with 3 reserved GDT blocks each
+        dev.patch(1024 + 0x20, &32u32.to_le_bytes());
+        dev.patch(1024 + 0xCE, &3u16.to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.num_block_groups(), 8);
+        let groups: Vec<usize> = (0..8)
+            .filter(|&group| ext2fs.group_has_superblock(group))
+            .collect();
+        assert_eq!(groups, vec![0, 1, 3, 5, 7]);
+        assert_eq!(ext2fs.total_reserved_gdt_blocks(), 15);
+    }
+
+    #[test]
+    fn reserved_gdt_blocks_sparse_super2() {
+        let path = std::path::PathBuf::from("ext2fs_sparse_super2.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+
+        assert_eq!(ext2fs.num_block_groups(), 8);
+        let groups: Vec<usize> = (0..8)
+            .filter(|&group| ext2fs.group_has_superblock(group))
+            .collect();
+        assert_eq!(groups, vec![0, 1, 7]);
+        assert_eq!(ext2fs.total_reserved_gdt_blocks(), 3 * 255);
+    }
+
+    #[test]
+    fn snapshot_info() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.snapshot_info(), None);
+
+        dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x180, &12u32.to_le_bytes());
+        dev.patch(1024 + 0x184, &3u32.to_le_bytes());
+        dev.patch(1024 + 0x188, &20u64.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(
+            ext2fs.snapshot_info(),
+            Some(SnapshotInfo {
+                inode: 12,
+                id: 3,
+                reserved_blocks: 20,
+                list_head: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn quota_inodes() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x240, &3u32.to_le_bytes());
+        dev.patch(1024 + 0x244, &4u32.to_le_bytes());
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        // The inodes are ignored unless the quota feature is enabled
+        assert_eq!(ext2fs.quota_inode(QuotaKind::User), None);
+
+        dev = ext2fs.device;
+        let ro_compat = 0x3u32 | RoCompatFeatures::QUOTA.bits();
+        dev.patch(1024 + 0x64, &ro_compat.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.quota_inode(QuotaKind::User), Some(3));
+        assert_eq!(ext2fs.quota_inode(QuotaKind::Group), Some(4));
+        assert_eq!(ext2fs.quota_inode(QuotaKind::Project), None);
+    }
+
+    #[test]
+    fn reserved_blocks() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert!((ext2fs.reserved_percentage() - 12.0 / 256.0 * 100.0).abs() < 1e-9);
+
+        assert!(matches!(
+            ext2fs.set_reserved_blocks(257),
+            Err(Error::InvalidArgument)
+        ));
+        ext2fs.set_reserved_blocks(64).unwrap();
+
+        // Remount to make sure the change made it to the device
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        assert!((ext2fs.reserved_percentage() - 25.0).abs() < 1e-9);
+        assert_eq!(ext2fs.num_blocks(), 256);
+    }
+
+    #[test]
+    fn reserved_blocks_update_backup_superblocks() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert!(ext2fs.group_has_superblock(1));
+
+        ext2fs.set_reserved_blocks(100).unwrap();
+
+        // The backup of group 1 is at the start of the group, block 2049
+        let backup = Ext2SuperBlock::parse(&ext2fs.read_fs_blocks(2049, 1).unwrap());
+        assert_eq!(backup.s_magic, 0xEF53);
+        assert_eq!(backup.s_r_blocks_count, 100);
+        assert_eq!(backup.s_block_group_nr, 1);
+
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        assert_eq!(ext2fs.reserved_blocks_count(), 100);
+        assert_eq!(ext2fs.superblock.s_block_group_nr, 0);
+    }
+
+    #[test]
+    fn reserved_blocks_sparse_super2_backups() {
+        let path = std::path::PathBuf::from("ext2fs_sparse_super2.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        // Groups 3 and 5 hold no backup, they start with their block bitmaps
+        let bitmaps: Vec<Vec<u8>> = [1537, 2561]
+            .iter()
+            .map(|&block| ext2fs.read_fs_blocks(block, 1).unwrap())
+            .collect();
+        ext2fs.set_reserved_blocks(100).unwrap();
+        for (&block, bitmap) in [1537, 2561].iter().zip(&bitmaps) {
+            assert_eq!(&ext2fs.read_fs_blocks(block, 1).unwrap(), bitmap);
+        }
+
+        // The backups of groups 1 and 7 are updated
+        for &(group, block) in &[(1, 513), (7, 3585)] {
+            let backup = Ext2SuperBlock::parse(&ext2fs.read_fs_blocks(block, 1).unwrap());
+            assert_eq!(backup.s_magic, 0xEF53);
+            assert_eq!(backup.s_r_blocks_count, 100);
+            assert_eq!(backup.s_block_group_nr, group);
+        }
+    }
+
+    #[test]
+    fn groups_start_at_the_first_data_block() {
+        // 1025 blocks, but block 0 comes before the first group of 1024 blocks
+        let path = std::path::PathBuf::from("ext2fs_trailing_block.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.num_block_groups(), 1);
+        assert!(ext2fs.group_descriptor(1).is_err());
+        assert_eq!(ext2fs.total_reserved_gdt_blocks(), 32);
+
+        // There is no group 1 whose backup superblock would go past the end of the device
+        let last_block = ext2fs.read_fs_blocks(1024, 1).unwrap();
+        ext2fs.set_reserved_blocks(10).unwrap();
+        assert_eq!(ext2fs.read_fs_blocks(1024, 1).unwrap(), last_block);
+
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        assert_eq!(ext2fs.reserved_blocks_count(), 10);
+    }
+
+    #[test]
+    fn mount_state() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.mount_state(), MountState::Clean);
+
+        dev = ext2fs.device;
+        dev.patch(1024 + 0x3A, &0u16.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.mount_state(), MountState::Dirty);
+
+        dev = ext2fs.device;
+        dev.patch(1024 + 0x3A, &(EXT2_VALID_FS | EXT2_ERROR_FS).to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.mount_state(), MountState::HasErrors);
+    }
+
+    #[test]
+    fn error_log() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.error_log(), None);
+
+        dev = ext2fs.device;
+        dev.patch(1024 + 0x194, &2u32.to_le_bytes());
+        dev.patch(1024 + 0x198, &1623268620u32.to_le_bytes());
+        dev.patch(1024 + 0x19C, &12u32.to_le_bytes());
+        dev.patch(1024 + 0x1A8, b"ext4_lookup\0");
+        dev.patch(1024 + 0x1C8, &1701u32.to_le_bytes());
+        ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let error_log = ext2fs.error_log().unwrap();
+        assert_eq!(error_log.count, 2);
+        assert_eq!(error_log.last, None);
+        let first = error_log.first.unwrap();
+        assert_eq!(first.time, 1623268620);
+        assert_eq!(
+            first.system_time(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1623268620)
+        );
+        assert_eq!(first.inode, 12);
+        assert_eq!(first.function, b"ext4_lookup");
+        assert_eq!(first.line, 1701);
+    }
+
+    #[test]
+    fn mount_opts_string() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.mount_opts_string(), None);
+
+        dev = ext2fs.device;
+        dev.patch(1024 + 0x200, b"nodelalloc,data=journal\0");
+        ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(
+            ext2fs.mount_opts_string().as_deref(),
+            Some("nodelalloc,data=journal")
+        );
+    }
+
+    #[test]
+    fn reject_unknown_checksum_type() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let mut dev = FileDevice::new(&path);
+        // Set metadata_csum in s_feature_ro_compat and leave s_checksum_type at 0
+        dev.patch(
+            1024 + 0x64,
+            &RoCompatFeatures::METADATA_CSUM.bits().to_le_bytes(),
+        );
+        assert!(matches!(Ext2Fs::new(dev), Err(Error::UnsupportedFeature)));
+    }
+
+    #[test]
+    fn reject_invalid_superblocks() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let patches: [(usize, u32); 16] = [
+            (0x00, 129),         // s_inodes_count, more than the single group holds
+            (0x04, 0),           // s_blocks_count
+            (0x14, 256),         // s_first_data_block, past the last block
+            (0x18, 7),           // s_log_block_size, 128KiB blocks
+            (0x18, 40),          // s_log_block_size, overflowing the shift
+            (0x18, 0xFFFF_FFFF), // s_log_block_size, 512 byte blocks
+            (0x20, 0),           // s_blocks_per_group
+            (0x20, 32769),       // s_blocks_per_group, more than a bitmap block tracks
+            (0x28, 0),           // s_inodes_per_group
+            (0x28, 32769),       // s_inodes_per_group, more than a bitmap block tracks
+            (0x54, 0),           // s_first_ino
+            (0x54, 129),         // s_first_ino, past the last inode
+            (0x58, 200),         // s_inode_size, not a power of two
+            (0x58, 64),          // s_inode_size, smaller than the original inode
+            (0x58, 8192),        // s_inode_size, larger than a block
+            (0x58, 0),           // s_inode_size
+        ];
+
+        for (offset, value) in patches {
+            let mut dev = FileDevice::new(&path);
+            if offset == 0x58 {
+                dev.patch(1024 + offset, &(value as u16).to_le_bytes());
+            } else {
+                dev.patch(1024 + offset, &value.to_le_bytes());
+            }
+            assert!(matches!(probe(&dev), Err(Error::InvalidSuperblock)));
+            assert!(matches!(Ext2Fs::new(dev), Err(Error::InvalidSuperblock)));
+        }
+    }
+
+    #[test]
+    fn reject_unsupported_incompat_features() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+
+        // filetype plus a bit no implementation knows about
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x60, &0x8000_0002u32.to_le_bytes());
+        assert!(matches!(
+            Ext2Fs::new(dev),
+            Err(Error::UnsupportedFeatures(0x8000_0000))
+        ));
+
+        // Inode block pointers can't be interpreted without extent support
+        let mut dev = FileDevice::new(&path);
+        let incompat = IncompatFeatures::FILETYPE | IncompatFeatures::EXTENTS;
+        dev.patch(1024 + 0x60, &incompat.bits().to_le_bytes());
+        let error = Ext2Fs::new(dev).err().unwrap();
+        assert!(matches!(error, Error::UnsupportedFeatures(0x40)));
+        assert_eq!(
+            error.to_string(),
+            "unsupported incompatible features: IncompatFeatures(EXTENTS)"
+        );
+    }
+
+    #[test]
+    fn unknown_ro_compat_features_force_read_only() {
+        let path = std::path::PathBuf::from("ext2fs.bin");
+        let dev = FileDevice::new(&path);
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        assert!(!ext2fs.is_read_only());
+
+        let mut dev = ext2fs.device;
+        dev.patch(1024 + 0x64, &0x8000_0003u32.to_le_bytes());
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert!(ext2fs.is_read_only());
+        assert_eq!(
+            ext2fs
+                .read_file(&ext2fs.read_inode(2).unwrap())
+                .unwrap()
+                .len(),
+            4096
+        );
+        assert!(matches!(
+            ext2fs.set_reserved_blocks(0),
+            Err(Error::ReadOnly)
+        ));
+        assert_eq!(ext2fs.reserved_blocks_count(), 12);
+
+        // A journal that still has to be replayed can be read, but not written
+        let mut dev = ext2fs.device;
+        dev.patch(1024 + 0x64, &0x3u32.to_le_bytes());
+        let incompat = IncompatFeatures::FILETYPE | IncompatFeatures::RECOVER;
+        dev.patch(1024 + 0x60, &incompat.bits().to_le_bytes());
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        assert!(ext2fs.is_read_only());
+        assert_eq!(ext2fs.mount_state(), MountState::NeedsRecovery);
+    }
+
+    #[test]
+    fn new_inodes_reserve_extra_isize() {
+        // Returns the i_extra_isize of a file created with the given superblock settings
+        let extra_isize = |want: u16, min: u16| {
+            let path = std::path::PathBuf::from("ext2fs_files.bin");
+            let mut dev = FileDevice::new(&path);
+            dev.patch(1024 + 0x15C, &min.to_le_bytes());
+            dev.patch(1024 + 0x15E, &want.to_le_bytes());
+            let mut ext2fs = Ext2Fs::new(dev).unwrap();
+            assert_eq!(ext2fs.inode_size(), 256);
+
+            let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+            let file = ext2fs.create_file(&root, "extra.bin").unwrap();
+            let (block, offset) = ext2fs.inode_location(file.number()).unwrap();
+            let data = ext2fs.read_fs_blocks(block, 1).unwrap();
+            u16::from_le_bytes([data[offset + 128], data[offset + 129]])
+        };
+
+        assert_eq!(extra_isize(32, 32), 32);
+        assert_eq!(extra_isize(64, 32), 64);
+        assert_eq!(extra_isize(0, 28), 28);
+        assert_eq!(extra_isize(0, 0), 32);
+        assert_eq!(extra_isize(16, 28), 28);
+        // Never more than what the inode can hold
+        assert_eq!(extra_isize(512, 0), 128);
+    }
+
+    #[test]
+    fn data_is_aligned_to_the_raid_stripe() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x170, &16u32.to_le_bytes());
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        assert_eq!(ext2fs.raid_stripe_width(), 16);
+        let free_blocks = ext2fs.free_blocks_count();
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut file = ext2fs.create_file(&root, "striped.bin").unwrap();
+        let data = vec![0x5A; 40 * 1024];
+        ext2fs.write_file(&mut file, 0, &data).unwrap();
+
+        // Each stripe of the file starts on a stripe boundary, the rest of it follows
+        let blocks: Vec<u32> = ext2fs
+            .block_map(&file)
+            .unwrap()
+            .iter()
+            .flat_map(|run| (run.physical..run.physical + run.len).map(|block| block as u32))
+            .collect();
+        assert_eq!(blocks.len(), 40);
+        for logical in [0, 16, 32] {
+            assert_eq!(blocks[logical] % 16, 0);
+        }
+        assert!((1..12).all(|logical| blocks[logical] == blocks[0] + logical as u32));
+        assert_eq!(ext2fs.read_file(&file).unwrap(), data);
+
+        // 40 data blocks plus the indirect block
+        assert_eq!(ext2fs.free_blocks_count(), free_blocks - 41);
+    }
+
+    #[test]
+    fn create_and_write_file() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let free_blocks = ext2fs.free_blocks_count();
+        let free_inodes = ext2fs.free_inodes_count();
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut file = ext2fs.create_file(&root, "new.bin").unwrap();
+        assert_eq!(file.size(), 0);
+        assert!(matches!(
+            ext2fs.create_file(&root, "new.bin"),
+            Err(Error::AlreadyExists(name)) if name == "new.bin"
+        ));
+        assert!(matches!(
+            ext2fs.create_file(&root, "a/b"),
+            Err(Error::InvalidArgument)
+        ));
+        let hello = ext2fs.lookup("/hello.txt").unwrap();
+        assert!(matches!(
+            ext2fs.create_file(&hello, "c"),
+            Err(Error::NotADirectory)
+        ));
+
+        // Large enough to need the single and double indirect blocks
+        let mut expected: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        ext2fs.write_file(&mut file, 0, &expected[..1000]).unwrap();
+        ext2fs
+            .write_file(&mut file, 1000, &expected[1000..])
+            .unwrap();
+        // Overwrite data across a block boundary
+        ext2fs.write_file(&mut file, 2046, b"abcd").unwrap();
+        expected[2046..2050].copy_from_slice(b"abcd");
+        assert_eq!(file.size(), 300_000);
+
+        // Remounting checks that the group descriptors still add up to the superblock counts
+        let dev = ext2fs.device;
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let file = ext2fs.lookup("/new.bin").unwrap();
+        assert_eq!(file.file_type(), FileType::Regular);
+        assert_eq!(file.size(), 300_000);
+        assert_eq!(ext2fs.read_file(&file).unwrap(), expected);
+
+        // 293 data blocks, the single indirect block and a double indirect block with one child
+        assert_eq!(free_blocks - ext2fs.free_blocks_count(), 296);
+        assert_eq!(file.sector_count(), 296 * 2);
+        assert_eq!(free_inodes - ext2fs.free_inodes_count(), 1);
+    }
+
+    #[test]
+    fn writes_through_stale_inodes() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        ext2fs.create_file(&root, "stale.bin").unwrap();
+        let free_blocks = ext2fs.free_blocks_count();
+
+        // Both copies are read before either write, the second one is out of date when used
+        let mut first = ext2fs.lookup("/stale.bin").unwrap();
+        let mut second = ext2fs.lookup("/stale.bin").unwrap();
+        ext2fs.write_file(&mut first, 0, &[1; 20000]).unwrap();
+        ext2fs.write_file(&mut second, 0, &[2; 20000]).unwrap();
+        assert_eq!(second.sector_count(), first.sector_count());
+
+        // The blocks allocated by the first write are reused, not leaked
+        let file = ext2fs.lookup("/stale.bin").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), vec![2; 20000]);
+        assert_eq!(
+            free_blocks - ext2fs.free_blocks_count(),
+            file.sector_count() / 2
+        );
+        assert_counts_match_bitmaps(&ext2fs);
+    }
+
+    /// Checks that the free counts of the superblock and of every group descriptor match the
+    /// bitmaps
+    fn assert_counts_match_bitmaps<T: BlockDevice>(ext2fs: &Ext2Fs<T>) {
+        let blocks_per_group = ext2fs.superblock.s_blocks_per_group as usize;
+        let inodes_per_group = ext2fs.superblock.s_inodes_per_group as usize;
+        let first_data_block = ext2fs.superblock.s_first_data_block as usize;
+        let free_bits = |bitmap: &[u8], count: usize| {
+            (0..count)
+                .filter(|&i| bitmap[i / 8] & (1 << (i % 8)) == 0)
+                .count()
+        };
+
+        let (mut free_blocks, mut free_inodes) = (0, 0);
+        for group in 0..ext2fs.num_block_groups {
+            let descriptor = ext2fs.group_descriptor(group).unwrap();
+            let group_blocks = blocks_per_group
+                .min(ext2fs.num_blocks() - first_data_block - group * blocks_per_group);
+            let bitmap = ext2fs
+                .read_fs_blocks(descriptor.block_bitmap() as usize, 1)
+                .unwrap();
+            assert_eq!(
+                free_bits(&bitmap, group_blocks),
+                descriptor.free_blocks_count() as usize
+            );
+            let bitmap = ext2fs
+                .read_fs_blocks(descriptor.inode_bitmap() as usize, 1)
+                .unwrap();
+            assert_eq!(
+                free_bits(&bitmap, inodes_per_group),
+                descriptor.free_inodes_count() as usize
+            );
+
+            free_blocks += descriptor.free_blocks_count() as u32;
+            free_inodes += descriptor.free_inodes_count() as u32;
+        }
+        assert_eq!(ext2fs.free_blocks_count(), free_blocks);
+        assert_eq!(ext2fs.free_inodes_count(), free_inodes);
+    }
+
+    #[test]
+    fn running_out_of_space_keeps_counts_consistent() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+        let free_blocks = ext2fs.free_blocks_count();
+
+        // Far more than the image can hold
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut file = ext2fs.create_file(&root, "fill.bin").unwrap();
+        let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 249) as u8).collect();
+        assert!(matches!(
+            ext2fs.write_file(&mut file, 0, &data),
+            Err(Error::NoSpaceLeft)
+        ));
+        assert_counts_match_bitmaps(&ext2fs);
+
+        // Every block allocated by the failed write belongs to the file
+        assert_eq!(ext2fs.free_blocks_count(), 0);
+        assert_eq!(file.sector_count() / 2, free_blocks);
+        let size = file.size() as usize;
+        assert_eq!(size % 1024, 0);
+
+        // Without free blocks, the root directory eventually can't grow for a new entry
+        let free_inodes = ext2fs.free_inodes_count();
+        let mut created = 0;
+        let error = loop {
+            let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+            let name = format!("{:0>200}", created);
+            match ext2fs.create_file(&root, &name) {
+                Ok(_) => created += 1,
+                Err(error) => break error,
+            }
+        };
+        assert!(matches!(error, Error::NoSpaceLeft));
+        assert_eq!(ext2fs.free_inodes_count(), free_inodes - created);
+        assert_counts_match_bitmaps(&ext2fs);
+
+        // Everything written before running out of space is there after remounting
+        let ext2fs = Ext2Fs::new(ext2fs.device).unwrap();
+        assert_counts_match_bitmaps(&ext2fs);
+        let file = ext2fs.lookup("/fill.bin").unwrap();
+        assert_eq!(ext2fs.read_file(&file).unwrap(), &data[..size]);
+    }
+
+    #[test]
+    fn create_files_until_directory_grows() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let dev = FileDevice::new(&path);
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let sub = ext2fs.lookup("/dir/sub").unwrap();
+        assert_eq!(sub.size(), 1024);
+        for i in 0..100 {
+            let name = format!("created_file_{:03}", i);
+            let mut file = ext2fs.create_file(&sub, &name).unwrap();
+            ext2fs.write_file(&mut file, 0, name.as_bytes()).unwrap();
+        }
+
+        // A sparse file, with data only in its last block
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        let mut sparse = ext2fs.create_file(&root, "sparse_new.bin").unwrap();
+        ext2fs.write_file(&mut sparse, 50_000, b"end").unwrap();
+        assert_eq!(sparse.sector_count(), 2 * 2);
+
+        let dev = ext2fs.device;
+        let ext2fs = Ext2Fs::new(dev).unwrap();
+        let sub = ext2fs.lookup("/dir/sub").unwrap();
+        assert!(sub.size() > 1024);
+        let names: Vec<String> = ext2fs
+            .read_dir(&sub)
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect();
+        assert_eq!(names.len(), 103);
+        assert!(names.contains(&"deep.txt".to_string()));
+        for i in 0..100 {
+            let name = format!("created_file_{:03}", i);
+            let file = ext2fs.lookup(&format!("/dir/sub/{}", name)).unwrap();
+            assert_eq!(ext2fs.read_file(&file).unwrap(), name.as_bytes());
+        }
+
+        let sparse = ext2fs.lookup("/sparse_new.bin").unwrap();
+        let data = ext2fs.read_file(&sparse).unwrap();
+        assert_eq!(data.len(), 50_003);
+        assert!(data[..50_000].iter().all(|&byte| byte == 0));
+        assert_eq!(&data[50_000..], b"end");
+    }
+
+    #[test]
+    fn writes_are_refused_on_read_only_filesystems() {
+        let path = std::path::PathBuf::from("ext2fs_files.bin");
+        let mut dev = FileDevice::new(&path);
+        dev.patch(1024 + 0x64, &0x8000_0003u32.to_le_bytes());
+        let mut ext2fs = Ext2Fs::new(dev).unwrap();
+
+        let root = ext2fs.read_inode(Ext2Fs::<FileDevice>::ROOT_INODE).unwrap();
+        assert!(matches!(
+            ext2fs.create_file(&root, "new.bin"),
+            Err(Error::ReadOnly)
+        ));
+        let mut hello = ext2fs.lookup("/hello.txt").unwrap();
+        assert!(matches!(
+            ext2fs.write_file(&mut hello, 0, b"Bye"),
+            Err(Error::ReadOnly)
+        ));
     }
 }
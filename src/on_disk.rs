@@ -0,0 +1,92 @@
+//! Safe, endian-aware (de)serialization of the on-disk structures. All fields are stored in
+//! little-endian byte order, without padding between them.
+
+use core::convert::TryInto;
+
+/// Field of an on-disk structure
+pub(crate) trait OnDisk: Sized {
+    /// Size of the field on disk, in bytes
+    const SIZE: usize;
+
+    /// Parses the field from the first `SIZE` bytes of `data`
+    fn parse(data: &[u8]) -> Self;
+
+    /// Serializes the field into the first `SIZE` bytes of `out`
+    fn serialize(&self, out: &mut [u8]);
+}
+
+macro_rules! impl_on_disk_for_int {
+    ($($ty:ty),*) => {
+        $(
+            impl OnDisk for $ty {
+                const SIZE: usize = core::mem::size_of::<$ty>();
+
+                fn parse(data: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(data[..Self::SIZE].try_into().unwrap())
+                }
+
+                fn serialize(&self, out: &mut [u8]) {
+                    out[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_on_disk_for_int!(u8, u16, u32, u64, i32);
+
+impl<T: OnDisk + Copy + Default, const N: usize> OnDisk for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn parse(data: &[u8]) -> Self {
+        let mut array = [T::default(); N];
+        for (i, element) in array.iter_mut().enumerate() {
+            *element = T::parse(&data[i * T::SIZE..]);
+        }
+        array
+    }
+
+    fn serialize(&self, out: &mut [u8]) {
+        for (i, element) in self.iter().enumerate() {
+            element.serialize(&mut out[i * T::SIZE..]);
+        }
+    }
+}
+
+/// Declares a structure stored on disk. The fields are laid out in declaration order, and
+/// `OnDisk` is implemented for the structure so that it can be parsed from and serialized into
+/// raw bytes.
+macro_rules! on_disk_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($field: $ty,)*
+        }
+
+        impl $crate::on_disk::OnDisk for $name {
+            const SIZE: usize = 0 $(+ <$ty as $crate::on_disk::OnDisk>::SIZE)*;
+
+            fn parse(data: &[u8]) -> Self {
+                let mut _offset = 0;
+                $(
+                    let $field = <$ty as $crate::on_disk::OnDisk>::parse(&data[_offset..]);
+                    _offset += <$ty as $crate::on_disk::OnDisk>::SIZE;
+                )*
+                $name { $($field,)* }
+            }
+
+            fn serialize(&self, out: &mut [u8]) {
+                let mut _offset = 0;
+                $(
+                    $crate::on_disk::OnDisk::serialize(&self.$field, &mut out[_offset..]);
+                    _offset += <$ty as $crate::on_disk::OnDisk>::SIZE;
+                )*
+            }
+        }
+    };
+}
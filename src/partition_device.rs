@@ -0,0 +1,59 @@
+use crate::{BlockDevice, Error};
+use alloc::vec::Vec;
+
+/// Block device adapter exposing a region of a larger device, such as a partition inside a
+/// whole-disk image. Block indices are relative to the start of the region.
+pub struct PartitionDevice<T: BlockDevice> {
+    device: T,
+    first_block: usize,
+}
+
+impl<T: BlockDevice> PartitionDevice<T> {
+    /// Wraps `device` so that its block 0 starts at `byte_offset`. The offset must be a multiple
+    /// of the device block size, which must be a power of two.
+    pub fn new(device: T, byte_offset: usize) -> Result<Self, Error> {
+        let block_size = device.get_block_size();
+        if !block_size.is_power_of_two() {
+            return Err(Error::UnsupportedBlockSize(block_size));
+        }
+        if byte_offset % block_size != 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(PartitionDevice {
+            device,
+            first_block: byte_offset / block_size,
+        })
+    }
+
+    /// Returns a reference to the underlying device
+    pub fn inner(&self) -> &T {
+        &self.device
+    }
+
+    /// Consumes the adapter and returns the underlying device
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for PartitionDevice<T> {
+    type Error = T::Error;
+
+    fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, T::Error> {
+        self.device
+            .read_blocks(self.first_block + index, num_blocks)
+    }
+
+    fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), T::Error> {
+        self.device.write_blocks(self.first_block + index, data)
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.device.get_block_size()
+    }
+
+    fn flush(&mut self) -> Result<(), T::Error> {
+        self.device.flush()
+    }
+}
@@ -0,0 +1,540 @@
+//! Write support: allocation of blocks and inodes, creation of files and writes to their contents
+
+use crate::dir;
+use crate::inode::Ext2Inode;
+use crate::on_disk::OnDisk;
+use crate::{
+    BlockDevice, Error, Ext2Fs, Ext2GroupDescriptor, FileType, IncompatFeatures, Inode,
+    RoCompatFeatures,
+};
+use alloc::string::ToString;
+use core::convert::TryInto;
+
+/// Inode flag: the directory is indexed with a hashed b-tree
+const EXT2_INDEX_FL: u32 = 0x1000;
+
+/// Largest file size that can be recorded without the large_file feature
+const EXT2_MAX_SMALL_FILE_SIZE: u64 = 0x7FFF_FFFF;
+
+/// Extra inode space used by the fields the kernel adds after the original 128 bytes
+const EXT4_DEFAULT_EXTRA_ISIZE: u16 = 32;
+
+/// Longest file name a directory entry can hold
+const EXT2_NAME_LEN: usize = 255;
+
+/// Returns the current time, in seconds since the Unix epoch
+#[cfg(feature = "std")]
+fn now() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/// Without `std` there is no clock to read, so modifications aren't timestamped
+#[cfg(not(feature = "std"))]
+fn now() -> u32 {
+    0
+}
+
+impl<T: BlockDevice> Ext2Fs<T> {
+    /// Creates an empty regular file called `name` in the directory described by `dir`, and
+    /// returns its inode. The directory inode is read again from the device, so copies of it
+    /// held by the caller are stale afterwards.
+    pub fn create_file(&mut self, dir: &Inode, name: &str) -> Result<Inode, Error> {
+        self.check_writable()?;
+        if name.is_empty()
+            || name.len() > EXT2_NAME_LEN
+            || name == "."
+            || name == ".."
+            || name.contains(['/', '\0'])
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut dir = self.read_inode(dir.number())?;
+        match self.find_entry(&dir, name) {
+            Ok(_) => return Err(Error::AlreadyExists(name.to_string())),
+            Err(Error::NotFound(_)) => {}
+            Err(error) => return Err(error),
+        }
+
+        // Keep the new inode close to its directory
+        let ino = self.alloc_inode(self.inode_group(dir.number()))?;
+        let inode = Inode::create(ino, FileType::Regular, 0o644, now());
+        let result = self
+            .write_new_inode(&inode)
+            .and_then(|_| self.add_dir_entry(&mut dir, name, &inode));
+        if let Err(error) = result {
+            // Don't leave behind an allocated inode that no directory entry points to
+            self.free_inode(ino)?;
+            self.write_counters()?;
+            return Err(error);
+        }
+        self.write_counters()?;
+        Ok(inode)
+    }
+
+    /// Writes `data` at byte `offset` of the file described by `inode`, allocating data and
+    /// indirect blocks as needed. Writing past the end of the file leaves a hole in between.
+    /// The inode is read again from the inode table, since `inode` may be out of date, then
+    /// updated, written back and copied into `inode`.
+    pub fn write_file(&mut self, inode: &mut Inode, offset: u64, data: &[u8]) -> Result<(), Error> {
+        self.write_file_partial(inode, offset, data).1
+    }
+
+    /// Like `write_file`, but also returns the number of bytes written. If the write fails
+    /// partway, those are on disk and covered by the size of the file.
+    pub(crate) fn write_file_partial(
+        &mut self,
+        inode: &mut Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> (usize, Result<(), Error>) {
+        // Another copy of the inode may have been written since this one was read, and the blocks
+        // it allocated would be lost if this copy was written back
+        *inode = match self.read_inode(inode.number()) {
+            Ok(current) => current,
+            Err(error) => return (0, Err(error)),
+        };
+        if let Err(error) = self.check_file_writable(inode, offset, data) {
+            return (0, Err(error));
+        }
+
+        // Even if the write fails partway, the inode has to be written back to keep the blocks
+        // allocated so far, and the size of what was written. Without it nothing was written.
+        let (written, result) = self.write_data(inode, offset, data);
+        inode.touch(now());
+        if let Err(error) = self.write_inode(inode).and_then(|()| self.write_counters()) {
+            return (0, Err(error));
+        }
+        (written, result)
+    }
+
+    /// Checks that `data` can be written at byte `offset` of the file, enabling large_file if
+    /// it makes the file too large without it
+    fn check_file_writable(
+        &mut self,
+        inode: &Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.check_writable()?;
+        match inode.file_type() {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Error::IsADirectory),
+            _ => return Err(Error::InvalidArgument),
+        }
+
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or(Error::InvalidArgument)?;
+        if end > EXT2_MAX_SMALL_FILE_SIZE
+            && !self
+                .superblock
+                .ro_compat_features()
+                .contains(RoCompatFeatures::LARGE_FILE)
+        {
+            self.superblock.s_feature_ro_compat |= RoCompatFeatures::LARGE_FILE.bits();
+            self.write_superblock()?;
+        }
+        Ok(())
+    }
+
+    /// Adds an entry for `inode` to the directory, extending it with a new block if none of the
+    /// existing ones has enough room
+    fn add_dir_entry(&mut self, dir: &mut Inode, name: &str, inode: &Inode) -> Result<(), Error> {
+        let file_type = if self
+            .superblock
+            .incompat_features()
+            .contains(IncompatFeatures::FILETYPE)
+        {
+            Some(inode.file_type().to_dir_entry())
+        } else {
+            None
+        };
+
+        let mut inserted = false;
+        for block in self.dir_blocks(dir)? {
+            if block == 0 {
+                return Err(Error::CorruptedDirectory);
+            }
+
+            let mut data = self.read_fs_blocks(block as usize, 1)?;
+            if dir::insert_entry(&mut data, inode.number(), name.as_bytes(), file_type)? {
+                self.write_fs_blocks(block as usize, &data)?;
+                inserted = true;
+                break;
+            }
+        }
+
+        if !inserted {
+            let mut data = vec![0; self.block_size];
+            dir::init_block(&mut data, inode.number(), name.as_bytes(), file_type);
+            let size = dir.size();
+            if let (_, Err(error)) = self.write_data(dir, size, &data) {
+                // Keep any indirect block allocated before running out of space
+                self.write_inode(dir)?;
+                return Err(error);
+            }
+        }
+
+        // Entries are inserted linearly, which would leave a hashed index out of date
+        dir.set_flags(dir.flags() & !EXT2_INDEX_FL);
+        dir.touch(now());
+        self.write_inode(dir)
+    }
+
+    /// Writes `data` at byte `offset` of the file, allocating blocks as needed and growing its
+    /// size. The inode is only updated in memory. Returns the number of bytes written, which the
+    /// size covers even if the write fails partway.
+    fn write_data(
+        &mut self,
+        inode: &mut Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> (usize, Result<(), Error>) {
+        let block_size = self.block_size as u64;
+        let end = offset + data.len() as u64;
+
+        // Keep the blocks of the file contiguous by allocating after the previous one
+        let mut near = None;
+        let mut position = offset;
+        while position < end {
+            let logical = (position / block_size) as usize;
+            let start = (position % block_size) as usize;
+            let len = (self.block_size - start).min((end - position) as usize);
+            let chunk = &data[(position - offset) as usize..][..len];
+
+            match self.write_block_data(inode, logical, start, chunk, near) {
+                Ok(block) => near = Some(block as usize + 1),
+                Err(error) => return ((position - offset) as usize, Err(error)),
+            }
+            position += len as u64;
+
+            if position > inode.size() {
+                inode.set_size(position);
+            }
+        }
+        (data.len(), Ok(()))
+    }
+
+    /// Writes `chunk` at byte `start` of the given logical block of the file, allocating it as
+    /// `map_block_for_write` does. Returns the physical block written.
+    fn write_block_data(
+        &mut self,
+        inode: &mut Inode,
+        logical: usize,
+        start: usize,
+        chunk: &[u8],
+        near: Option<usize>,
+    ) -> Result<u32, Error> {
+        let (block, allocated) = self.map_block_for_write(inode, logical, near)?;
+        if chunk.len() == self.block_size {
+            self.write_fs_blocks(block as usize, chunk)?;
+        } else {
+            // Partial block, keep the rest of its contents. New blocks start zeroed.
+            let mut buffer = if allocated {
+                vec![0; self.block_size]
+            } else {
+                self.read_fs_blocks(block as usize, 1)?
+            };
+            buffer[start..start + chunk.len()].copy_from_slice(chunk);
+            self.write_fs_blocks(block as usize, &buffer)?;
+        }
+        Ok(block)
+    }
+
+    /// Returns the physical block backing the given logical block of the file, allocating it
+    /// and any missing indirect block on the way, from block `near` onwards if given. The
+    /// returned flag is true if the data block was allocated by this call.
+    fn map_block_for_write(
+        &mut self,
+        inode: &mut Inode,
+        logical: usize,
+        near: Option<usize>,
+    ) -> Result<(u32, bool), Error> {
+        // Past what the triple indirect block can map
+        let (slot, path) = self.block_path(logical).ok_or(Error::InvalidArgument)?;
+
+        let goal = self.inode_group(inode.number());
+        let sectors_per_block = (self.block_size / 512) as u32;
+
+        // On RAID storage, data that starts a stripe in the file also starts a stripe on disk,
+        // so that writing it whole doesn't need a read-modify-write of the parity
+        let stripe_width = self.raid_stripe_width() as usize;
+        let data_align = if stripe_width > 1 && logical % stripe_width == 0 {
+            stripe_width
+        } else {
+            1
+        };
+
+        let mut block = inode.block_pointers()[slot];
+        let mut allocated = false;
+        if block == 0 {
+            let align = if path.is_empty() { data_align } else { 1 };
+            block = self.alloc_block(goal, near, align)?;
+            allocated = true;
+            inode.set_block_pointer(slot, block);
+            inode.add_sectors(sectors_per_block);
+            if !path.is_empty() {
+                self.write_fs_blocks(block as usize, &vec![0; self.block_size])?;
+            }
+        }
+
+        for (level, &index) in path.iter().enumerate() {
+            if block as usize >= self.num_blocks() {
+                return Err(Error::CorruptedInode);
+            }
+
+            let mut data = self.read_fs_blocks(block as usize, 1)?;
+            let pointer = &mut data[index * 4..index * 4 + 4];
+            let mut next = u32::from_le_bytes((&*pointer).try_into().unwrap());
+            allocated = false;
+            if next == 0 {
+                let align = if level + 1 == path.len() {
+                    data_align
+                } else {
+                    1
+                };
+                next = self.alloc_block(goal, near, align)?;
+                allocated = true;
+                inode.add_sectors(sectors_per_block);
+                pointer.copy_from_slice(&next.to_le_bytes());
+                self.write_fs_blocks(block as usize, &data)?;
+                if level + 1 < path.len() {
+                    self.write_fs_blocks(next as usize, &vec![0; self.block_size])?;
+                }
+            }
+            block = next;
+        }
+
+        if block as usize >= self.num_blocks() {
+            return Err(Error::CorruptedInode);
+        }
+        Ok((block, allocated))
+    }
+
+    /// Returns the block group holding the given inode
+    fn inode_group(&self, ino: u32) -> usize {
+        ((ino - 1) / self.superblock.s_inodes_per_group) as usize
+    }
+
+    /// Allocates a free block, searching from block `near` if given or else from the start of
+    /// the block group `goal`. Blocks whose number is a multiple of `align` are preferred, if
+    /// there are any left.
+    fn alloc_block(
+        &mut self,
+        goal: usize,
+        near: Option<usize>,
+        align: usize,
+    ) -> Result<u32, Error> {
+        if align > 1 {
+            if let Some(block) = self.try_alloc_block(goal, near, align)? {
+                return Ok(block);
+            }
+        }
+        self.try_alloc_block(goal, near, 1)?
+            .ok_or(Error::NoSpaceLeft)
+    }
+
+    /// Allocates the first free block whose number is a multiple of `align`, see `alloc_block`
+    fn try_alloc_block(
+        &mut self,
+        goal: usize,
+        near: Option<usize>,
+        align: usize,
+    ) -> Result<Option<u32>, Error> {
+        let blocks_per_group = self.superblock.s_blocks_per_group as usize;
+        let first_data_block = self.superblock.s_first_data_block as usize;
+
+        let (goal, goal_index) = match near {
+            Some(block) if block >= first_data_block && block < self.num_blocks() => {
+                let block = block - first_data_block;
+                (block / blocks_per_group, block % blocks_per_group)
+            }
+            _ => (goal, 0),
+        };
+
+        // The goal group is searched from the goal block first, and whole at the end
+        let groups = (goal..self.num_block_groups)
+            .map(|group| (group, if group == goal { goal_index } else { 0 }))
+            .chain((0..goal).map(|group| (group, 0)))
+            .chain(core::iter::once((goal, 0)).filter(|_| goal_index > 0));
+        for (group, start) in groups {
+            let descriptor = &self.group_descriptors[group];
+            if descriptor.bg_free_blocks_count == 0 {
+                continue;
+            }
+
+            // The last group may be shorter than the others
+            let first_block = first_data_block + group * blocks_per_group;
+            let group_blocks = blocks_per_group.min(self.num_blocks() - first_block);
+            let index = match self.alloc_bit(
+                descriptor.bg_block_bitmap as usize,
+                start,
+                group_blocks,
+                |index| (first_block + index) % align == 0,
+            )? {
+                Some(index) => index,
+                None if align > 1 || start > 0 => continue,
+                None => return Err(Error::CorruptedGroupDescriptors),
+            };
+
+            self.group_descriptors[group].bg_free_blocks_count -= 1;
+            self.superblock.s_free_blocks_count -= 1;
+            self.dirty_groups.insert(group);
+            return Ok(Some((first_block + index) as u32));
+        }
+        Ok(None)
+    }
+
+    /// Allocates a free inode for a regular file, preferably from the block group `goal`
+    fn alloc_inode(&mut self, goal: usize) -> Result<u32, Error> {
+        let inodes_per_group = self.superblock.s_inodes_per_group as usize;
+        let first_ino = if self.revision_level() >= crate::EXT2_DYNAMIC_REV {
+            self.superblock.s_first_ino as usize
+        } else {
+            11
+        };
+
+        for group in (goal..self.num_block_groups).chain(0..goal) {
+            let descriptor = &self.group_descriptors[group];
+            if descriptor.bg_free_inodes_count == 0 {
+                continue;
+            }
+
+            // Inodes before the first non-reserved one are never handed out
+            let first_index = (first_ino - 1).saturating_sub(group * inodes_per_group);
+            let index = self
+                .alloc_bit(
+                    descriptor.bg_inode_bitmap as usize,
+                    first_index,
+                    inodes_per_group,
+                    |_| true,
+                )?
+                .ok_or(Error::CorruptedGroupDescriptors)?;
+
+            self.group_descriptors[group].bg_free_inodes_count -= 1;
+            self.superblock.s_free_inodes_count -= 1;
+            self.dirty_groups.insert(group);
+            return Ok((group * inodes_per_group + index + 1) as u32);
+        }
+        Err(Error::NoSpaceLeft)
+    }
+
+    /// Releases an inode allocated by `alloc_inode`, clearing its slot in the inode table
+    fn free_inode(&mut self, ino: u32) -> Result<(), Error> {
+        let inodes_per_group = self.superblock.s_inodes_per_group as usize;
+        let group = self.inode_group(ino);
+        let index = (ino as usize - 1) % inodes_per_group;
+
+        let (block, offset) = self.inode_location(ino)?;
+        let mut data = self.read_fs_blocks(block, 1)?;
+        for byte in data[offset..offset + self.inode_size()].iter_mut() {
+            *byte = 0;
+        }
+        self.write_fs_blocks(block, &data)?;
+
+        let bitmap_block = self.group_descriptors[group].bg_inode_bitmap as usize;
+        let mut bitmap = self.read_fs_blocks(bitmap_block, 1)?;
+        bitmap[index / 8] &= !(1 << (index % 8));
+        self.write_fs_blocks(bitmap_block, &bitmap)?;
+
+        self.group_descriptors[group].bg_free_inodes_count += 1;
+        self.superblock.s_free_inodes_count += 1;
+        self.dirty_groups.insert(group);
+        Ok(())
+    }
+
+    /// Writes the superblock and the descriptors of the block groups whose free counts changed.
+    /// Allocations only update the counts in memory, so that a write allocating many blocks
+    /// updates them on the device once.
+    fn write_counters(&mut self) -> Result<(), Error> {
+        if self.dirty_groups.is_empty() {
+            return Ok(());
+        }
+
+        for group in self.dirty_groups.clone() {
+            self.write_group_descriptor(group)?;
+        }
+        self.write_superblock()?;
+        self.dirty_groups.clear();
+        Ok(())
+    }
+
+    /// Finds the first clear bit in `start..end` of the bitmap stored in `block` that `accept`
+    /// allows, sets it and writes the bitmap back. Returns `None` if there is no such bit.
+    fn alloc_bit(
+        &mut self,
+        block: usize,
+        start: usize,
+        end: usize,
+        accept: impl Fn(usize) -> bool,
+    ) -> Result<Option<usize>, Error> {
+        let mut bitmap = self.read_fs_blocks(block, 1)?;
+        let end = end.min(bitmap.len() * 8);
+        let index = match (start..end)
+            .find(|&index| bitmap[index / 8] & (1 << (index % 8)) == 0 && accept(index))
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        bitmap[index / 8] |= 1 << (index % 8);
+        self.write_fs_blocks(block, &bitmap)?;
+        Ok(Some(index))
+    }
+
+    fn write_group_descriptor(&mut self, group: usize) -> Result<(), Error> {
+        let descriptors_per_block = self.block_size / Ext2GroupDescriptor::SIZE;
+        let block = self.superblock.s_first_data_block as usize + 1 + group / descriptors_per_block;
+        let offset = (group % descriptors_per_block) * Ext2GroupDescriptor::SIZE;
+
+        let mut data = self.read_fs_blocks(block, 1)?;
+        self.group_descriptors[group].serialize(&mut data[offset..]);
+        self.write_fs_blocks(block, &data)
+    }
+
+    /// Writes the inode back to the inode table. Fields past the original 128 byte structure are
+    /// left untouched.
+    fn write_inode(&mut self, inode: &Inode) -> Result<(), Error> {
+        let (block, offset) = self.inode_location(inode.number())?;
+        let mut data = self.read_fs_blocks(block, 1)?;
+        inode.raw().serialize(&mut data[offset..]);
+        self.write_fs_blocks(block, &data)
+    }
+
+    /// Returns the extra inode space new inodes reserve. Like the kernel, it falls back to the
+    /// minimum guaranteed space and then to the size of the fields it knows about when the
+    /// superblock doesn't ask for a size, and never goes below the minimum.
+    fn new_extra_isize(&self) -> u16 {
+        let want = match (self.want_extra_isize(), self.min_extra_isize()) {
+            (0, 0) => EXT4_DEFAULT_EXTRA_ISIZE,
+            (0, min) => min,
+            (want, _) => want,
+        };
+        want.max(self.min_extra_isize())
+    }
+
+    /// Writes a newly allocated inode, clearing whatever a previous user of the slot left in it.
+    /// Large inodes reserve the extra space the superblock asks new inodes to have.
+    fn write_new_inode(&mut self, inode: &Inode) -> Result<(), Error> {
+        let inode_size = self.inode_size();
+        let (block, offset) = self.inode_location(inode.number())?;
+
+        let mut data = self.read_fs_blocks(block, 1)?;
+        let slot = &mut data[offset..offset + inode_size];
+        for byte in slot.iter_mut() {
+            *byte = 0;
+        }
+        inode.raw().serialize(slot);
+        if inode_size > Ext2Inode::SIZE {
+            let extra_isize = self
+                .new_extra_isize()
+                .min((inode_size - Ext2Inode::SIZE) as u16);
+            slot[Ext2Inode::SIZE..Ext2Inode::SIZE + 2].copy_from_slice(&extra_isize.to_le_bytes());
+        }
+        self.write_fs_blocks(block, &data)
+    }
+}
@@ -0,0 +1,150 @@
+use rext2fs::{BlockDevice, Error, Ext2Fs, FileType, Inode};
+use std::cell::RefCell;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+/// Device backed by an image loaded in memory. Clones share the same data, so that an image can
+/// be written through one device and read back through another.
+#[derive(Clone)]
+struct FileDevice {
+    data: Rc<RefCell<Vec<u8>>>,
+    block_size: usize,
+}
+
+impl FileDevice {
+    fn new(path: &std::path::Path, block_size: usize) -> Self {
+        let mut file = std::fs::File::open(path).unwrap();
+        let mut data = vec![];
+        file.read_to_end(&mut data).unwrap();
+        FileDevice {
+            data: Rc::new(RefCell::new(data)),
+            block_size,
+        }
+    }
+}
+
+impl BlockDevice for FileDevice {
+    type Error = ();
+
+    fn read_blocks(&self, index: usize, num_blocks: usize) -> Result<Vec<u8>, ()> {
+        let start = index * self.block_size;
+        Ok(self.data.borrow()[start..start + num_blocks * self.block_size].to_vec())
+    }
+
+    fn write_blocks(&mut self, index: usize, data: &[u8]) -> Result<(), ()> {
+        // Devices can only be written in whole blocks
+        assert_eq!(data.len() % self.block_size, 0);
+        let start = index * self.block_size;
+        self.data.borrow_mut()[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+/// Walks the whole tree below `dir`, recording every entry and the contents of every regular
+/// file
+fn walk<T: BlockDevice>(
+    ext2fs: &Ext2Fs<T>,
+    dir: &Inode,
+    path: &str,
+    out: &mut Vec<(String, u32, Vec<u8>)>,
+) {
+    for entry in ext2fs.read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+
+        let path = format!("{}/{}", path, entry.name);
+        let inode = ext2fs.read_inode(entry.inode).unwrap();
+        match entry.file_type {
+            FileType::Directory => {
+                out.push((path.clone(), entry.inode, vec![]));
+                walk(ext2fs, &inode, &path, out);
+            }
+            FileType::Regular => {
+                out.push((path, entry.inode, ext2fs.read_file(&inode).unwrap()));
+            }
+            _ => out.push((path, entry.inode, vec![])),
+        }
+    }
+}
+
+fn read_tree<T: BlockDevice>(ext2fs: &Ext2Fs<T>) -> Vec<(String, u32, Vec<u8>)> {
+    let root = ext2fs.read_inode(2).unwrap();
+    let mut tree = vec![];
+    walk(ext2fs, &root, "", &mut tree);
+    tree
+}
+
+fn read_image(image: &str, device_block_size: usize) -> Vec<(String, u32, Vec<u8>)> {
+    let path = std::path::PathBuf::from(image);
+    let dev = FileDevice::new(&path, device_block_size);
+    read_tree(&Ext2Fs::new(dev).unwrap())
+}
+
+#[test]
+fn identical_results_for_any_device_block_size() {
+    // ext2fs.bin uses 4KiB blocks, ext2fs_files.bin 1KiB blocks
+    for image in ["ext2fs.bin", "ext2fs_files.bin"] {
+        let reference = read_image(image, 1024);
+        assert!(!reference.is_empty());
+
+        for device_block_size in [512, 4096] {
+            assert_eq!(read_image(image, device_block_size), reference);
+        }
+    }
+}
+
+#[test]
+fn identical_writes_for_any_device_block_size() {
+    let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+    for image in ["ext2fs.bin", "ext2fs_files.bin"] {
+        let mut trees = vec![];
+        for device_block_size in [512, 1024, 4096] {
+            let path = std::path::PathBuf::from(image);
+            let dev = FileDevice::new(&path, device_block_size);
+            let mut ext2fs = Ext2Fs::new(dev.clone()).unwrap();
+
+            let root = ext2fs.read_inode(2).unwrap();
+            let mut file = ext2fs.create_file(&root, "written.bin").unwrap();
+            ext2fs.write_file(&mut file, 0, &contents).unwrap();
+            ext2fs.write_file(&mut file, 1000, b"overwritten").unwrap();
+            drop(ext2fs);
+
+            // Read the result back with an unrelated device block size
+            let dev = FileDevice {
+                block_size: 2048,
+                ..dev
+            };
+            trees.push(read_tree(&Ext2Fs::new(dev).unwrap()));
+        }
+
+        let mut expected = contents.clone();
+        expected[1000..1011].copy_from_slice(b"overwritten");
+        let written = trees[0].iter().find(|(path, _, _)| path == "/written.bin");
+        assert_eq!(written.unwrap().2, expected);
+        assert!(trees.iter().all(|tree| *tree == trees[0]));
+    }
+}
+
+#[test]
+fn reject_unsupported_device_block_sizes() {
+    let path = std::path::PathBuf::from("ext2fs.bin");
+    for device_block_size in [0, 1000, 1536] {
+        let dev = FileDevice::new(&path, device_block_size);
+        assert!(matches!(
+            Ext2Fs::new(dev),
+            Err(Error::UnsupportedBlockSize(size)) if size == device_block_size
+        ));
+        let dev = FileDevice::new(&path, device_block_size);
+        assert!(matches!(
+            Ext2Fs::new_at_offset(dev, 0),
+            Err(Error::UnsupportedBlockSize(size)) if size == device_block_size
+        ));
+    }
+}